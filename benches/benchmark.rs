@@ -99,20 +99,13 @@ criterion_main!(benches);
 
 fn spam_limit_orders(book: &mut OrderBook, count: u32) {
     for i in 0..count {
-        black_box(book.add_limit_order(Side::Buy, 100, 100, i.into(), i.into(), TimeInForce::GTC));
+        black_box(book.add_limit_order(Side::Buy, 100, 100, i, i, TimeInForce::GTC));
     }
 }
 
 fn spam_limit_orders_with_variance(book: &mut OrderBook, count: u32, variance: usize) {
     for i in 0..count {
-        black_box(book.add_limit_order(
-            Side::Buy,
-            100,
-            100 % variance,
-            i.into(),
-            i.into(),
-            TimeInForce::GTC,
-        ));
+        black_box(book.add_limit_order(Side::Buy, 100, 100 % variance, i, i, TimeInForce::GTC));
     }
 }
 
@@ -135,15 +128,8 @@ fn spam_limit_and_direct_market_orders(
         let price = price_f64.max(1.0).min(usize::MAX as f64) as usize;
         let quantity = quantity_f64.max(1.0).min(usize::MAX as f64) as usize;
 
-        black_box(book.add_limit_order(
-            Side::Buy,
-            quantity,
-            price,
-            i.into(),
-            i.into(),
-            TimeInForce::GTC,
-        ));
-        black_box(book.add_market_order(Side::Sell, quantity, i.into(), i.into()));
+        black_box(book.add_limit_order(Side::Buy, quantity, price, i, i, TimeInForce::GTC));
+        black_box(book.add_market_order(Side::Sell, quantity, i, i));
     }
 }
 
@@ -167,17 +153,10 @@ fn spam_limit_and_occasional_market_orders(
         let price = price_f64.max(1.0).min(usize::MAX as f64) as usize;
         let quantity = quantity_f64.max(1.0).min(usize::MAX as f64) as usize;
 
-        black_box(book.add_limit_order(
-            Side::Buy,
-            quantity,
-            price,
-            i.into(),
-            i.into(),
-            TimeInForce::GTC,
-        ));
+        black_box(book.add_limit_order(Side::Buy, quantity, price, i, i, TimeInForce::GTC));
 
         if i % market_order_frequency == 0 {
-            black_box(book.add_market_order(Side::Sell, quantity, i.into(), i.into()));
+            black_box(book.add_market_order(Side::Sell, quantity, i, i));
         }
     }
 }
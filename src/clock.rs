@@ -0,0 +1,75 @@
+// clock.rs
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+// source of the current time for an order book, swap it out to drive
+// time dependent behaviour (e.g. GTD expiry) deterministically
+pub trait Clock: Debug + Send {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// clock that only moves when told to, clones share the same time so one
+// handle can be given to the order book and another kept to advance it
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_manual_clock_advance() {
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let handle = clock.clone();
+        handle.advance(Duration::seconds(90));
+        assert_eq!(clock.now(), start + Duration::seconds(90));
+    }
+
+    #[test]
+    fn test_manual_clock_set() {
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let later = Utc.with_ymd_and_hms(2023, 11, 15, 16, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        clock.set(later);
+        assert_eq!(clock.now(), later);
+    }
+}
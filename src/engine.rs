@@ -0,0 +1,281 @@
+// engine.rs
+use crate::{
+    order::Order,
+    order_book::{CancelResult, InProcessOrder, OrderBook, OrderRef},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{atomic::AtomicU32, mpsc::Sender, Arc},
+};
+
+// one order book per symbol plus the state of the users trading on them
+#[derive(Debug)]
+pub struct Engine {
+    order_books: BTreeMap<String, OrderBook>,
+    // users stopped by the kill switch, their new orders are rejected
+    disabled_users: BTreeSet<u32>,
+    // handed to the order books created from now on
+    trading_enabled: bool,
+    // shared by every book so ids the books assign are unique across symbols
+    next_id: Arc<AtomicU32>,
+    tx: Option<Sender<String>>,
+}
+
+impl Engine {
+    pub fn new(trading_enabled: bool, tx: Option<Sender<String>>) -> Engine {
+        Engine {
+            order_books: BTreeMap::new(),
+            disabled_users: BTreeSet::new(),
+            trading_enabled,
+            next_id: Arc::new(AtomicU32::new(u32::MAX)),
+            tx,
+        }
+    }
+
+    pub fn set_trading_enabled(&mut self, trading_enabled: bool) {
+        self.trading_enabled = trading_enabled;
+    }
+
+    fn log(&self, msg: String) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    pub fn order_book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.order_books.get(symbol)
+    }
+
+    // the order book of `symbol`, created on first use. It stays private so
+    // every submission goes through the kill switch
+    fn order_book_mut(&mut self, symbol: &str) -> &mut OrderBook {
+        let (trading_enabled, tx, next_id) = (self.trading_enabled, &self.tx, &self.next_id);
+        self.order_books
+            .entry(symbol.to_string())
+            .or_insert_with(|| {
+                let mut order_book =
+                    OrderBook::new(symbol.to_string(), trading_enabled, tx.clone());
+                order_book.set_id_counter(next_id.clone());
+                order_book
+            })
+    }
+
+    pub fn add_order(&mut self, symbol: &str, order: Order) -> InProcessOrder {
+        if self.disabled_users.contains(&order.user_id) {
+            self.log(format!("R, {:?}, {:?}", order.user_id, order.id));
+            let mut rejected = InProcessOrder::new(order.quantity);
+            rejected.err = Some("User disabled".to_string());
+            return rejected;
+        }
+        self.order_book_mut(symbol).add_order(order)
+    }
+
+    // like `add_order` but the book assigns the order id, see `OrderBook::submit_order`
+    pub fn submit_order(
+        &mut self,
+        symbol: &str,
+        order: Order,
+        client_order_id: &str,
+    ) -> (Option<u32>, InProcessOrder) {
+        if self.disabled_users.contains(&order.user_id) {
+            self.log(format!("R, {:?}, -, {}", order.user_id, client_order_id));
+            let mut rejected = InProcessOrder::new(order.quantity);
+            rejected.err = Some("User disabled".to_string());
+            return (None, rejected);
+        }
+        self.order_book_mut(symbol)
+            .submit_order(order, client_order_id)
+    }
+
+    // cancels carry no symbol and ids are picked per user, so the cancel goes
+    // to the book holding the user's own open order, then to a book that knows
+    // the order at all, and any book reports the cancel reject otherwise
+    pub fn cancel_order(&mut self, user_id: u32, order_ref: impl Into<OrderRef>) -> CancelResult {
+        let order_ref = order_ref.into();
+        let symbol = self
+            .order_books
+            .iter()
+            .find(|(_, order_book)| order_book.find_order(user_id, order_ref.clone()).is_some())
+            .or_else(|| {
+                self.order_books
+                    .iter()
+                    .find(|(_, order_book)| order_book.knows_order_ref(user_id, order_ref.clone()))
+            })
+            .or_else(|| self.order_books.iter().next())
+            .map(|(symbol, _)| symbol.clone());
+        match symbol {
+            Some(symbol) => self
+                .order_books
+                .get_mut(&symbol)
+                .unwrap()
+                .cancel_order_user(user_id, order_ref),
+            None => CancelResult::UnknownOrder,
+        }
+    }
+
+    // kill switch: cancels every order of `user_id` in every book and rejects
+    // their new orders until the user is enabled again
+    pub fn disable_user(&mut self, user_id: u32) -> Vec<Order> {
+        self.disabled_users.insert(user_id);
+        self.order_books
+            .values_mut()
+            .flat_map(|order_book| order_book.cancel_user_orders(user_id))
+            .collect()
+    }
+
+    // returns false when the user was not disabled
+    pub fn enable_user(&mut self, user_id: u32) -> bool {
+        self.disabled_users.remove(&user_id)
+    }
+
+    pub fn is_user_disabled(&self, user_id: u32) -> bool {
+        self.disabled_users.contains(&user_id)
+    }
+
+    // drops every order book, disabled users stay disabled
+    pub fn flush(&mut self) {
+        self.order_books.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        order::{OrderType, TimeInForce},
+        side::Side,
+    };
+    use std::sync::mpsc::{self, Receiver};
+
+    fn engine() -> (Engine, Receiver<String>) {
+        let (tx, rx) = mpsc::channel();
+        (Engine::new(true, Some(tx)), rx)
+    }
+
+    fn limit_order(side: Side, price: usize, user_id: u32, id: u32) -> Order {
+        Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            price,
+            10,
+        )
+    }
+
+    #[test]
+    fn test_disable_user_cancels_in_every_book() {
+        let (mut engine, rx) = engine();
+        engine.add_order("AAPL", limit_order(Side::Buy, 100, 1, 1));
+        engine.add_order("AAPL", limit_order(Side::Buy, 99, 2, 2));
+        engine.add_order("MSFT", limit_order(Side::Sell, 200, 1, 3));
+        rx.try_iter().count();
+
+        let cancelled: Vec<u32> = engine.disable_user(1).iter().map(|o| o.id).collect();
+        assert_eq!(cancelled, vec![1, 3]);
+        assert!(engine.is_user_disabled(1));
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec!["M, 1", "B, B, 99, 10", "M, 1", "B, S, -, -"]
+        );
+        assert!(engine.order_book("AAPL").unwrap().knows_order(2));
+    }
+
+    #[test]
+    fn test_disabled_user_is_rejected_until_enabled() {
+        let (mut engine, rx) = engine();
+        engine.disable_user(1);
+        let order = engine.add_order("AAPL", limit_order(Side::Buy, 100, 1, 1));
+        assert_eq!(order.err, Some("User disabled".to_string()));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["R, 1, 1"]);
+        assert!(engine.order_book("AAPL").is_none());
+
+        assert!(engine.enable_user(1));
+        assert!(!engine.enable_user(1));
+        let order = engine.add_order("AAPL", limit_order(Side::Buy, 100, 1, 1));
+        assert!(order.err.is_none());
+    }
+
+    #[test]
+    fn test_rejected_submissions_get_no_id() {
+        let (mut engine, rx) = engine();
+        engine.submit_order("AAPL", limit_order(Side::Buy, 100, 1, 0), "a");
+        rx.try_iter().count();
+        // a taken ClOrdID and a disabled user are rejected alike
+        let (id, order) = engine.submit_order("AAPL", limit_order(Side::Buy, 99, 1, 0), "a");
+        assert_eq!(id, None);
+        assert!(order.err.is_some());
+        engine.disable_user(1);
+        rx.try_iter().count();
+        let (id, order) = engine.submit_order("AAPL", limit_order(Side::Buy, 99, 1, 0), "b");
+        assert_eq!(id, None);
+        assert_eq!(order.err, Some("User disabled".to_string()));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["R, 1, -, b"]);
+    }
+
+    #[test]
+    fn test_cancel_order_finds_the_book() {
+        let (mut engine, _rx) = engine();
+        assert_eq!(engine.cancel_order(1, 1), CancelResult::UnknownOrder);
+        engine.add_order("AAPL", limit_order(Side::Buy, 100, 1, 1));
+        engine.add_order("MSFT", limit_order(Side::Buy, 100, 1, 2));
+        assert!(matches!(
+            engine.cancel_order(1, 2),
+            CancelResult::Cancelled(_)
+        ));
+        assert_eq!(engine.cancel_order(1, 3), CancelResult::UnknownOrder);
+    }
+
+    #[test]
+    fn test_cancel_by_client_order_id() {
+        let (mut engine, rx) = engine();
+        engine.submit_order("AAPL", limit_order(Side::Buy, 100, 1, 0), "a");
+        let (id, _) = engine.submit_order("MSFT", limit_order(Side::Buy, 100, 1, 0), "b");
+        let id = id.unwrap();
+        rx.try_iter().count();
+        assert!(matches!(
+            engine.cancel_order(1, "b"),
+            CancelResult::Cancelled(order) if order.id == id
+        ));
+        assert_eq!(engine.cancel_order(1, "c"), CancelResult::UnknownOrder);
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![
+                format!("A, 1, {}, b", id),
+                "B, B, -, -".to_string(),
+                "CR, 1, -, UNKNOWN_ORDER, c".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancel_order_routes_to_the_owner() {
+        let (mut engine, _rx) = engine();
+        engine.add_order("AAPL", limit_order(Side::Buy, 100, 1, 5));
+        engine.add_order("MSFT", limit_order(Side::Buy, 100, 2, 5));
+        assert!(matches!(
+            engine.cancel_order(2, 5),
+            CancelResult::Cancelled(order) if order.user_id == 2
+        ));
+        assert!(engine.order_book("AAPL").unwrap().order(5).is_some());
+        assert!(engine.order_book("MSFT").unwrap().order(5).is_none());
+    }
+
+    #[test]
+    fn test_submitted_ids_are_unique_across_books() {
+        let (mut engine, _rx) = engine();
+        let (a_id, _) = engine.submit_order("AAPL", limit_order(Side::Buy, 100, 1, 0), "a");
+        let (b_id, _) = engine.submit_order("MSFT", limit_order(Side::Buy, 100, 1, 0), "b");
+        let (a_id, b_id) = (a_id.unwrap(), b_id.unwrap());
+        assert_ne!(a_id, b_id);
+        assert!(matches!(
+            engine.cancel_order(1, b_id),
+            CancelResult::Cancelled(order) if order.client_order_id.as_deref() == Some("b")
+        ));
+        assert!(engine.order_book("AAPL").unwrap().order(a_id).is_some());
+        assert!(engine.order_book("MSFT").unwrap().order(b_id).is_none());
+    }
+}
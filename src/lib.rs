@@ -1,5 +1,10 @@
+pub mod clock;
+pub mod engine;
 pub mod order;
 pub mod order_book;
 mod order_queue;
 mod order_side;
+pub mod scale_order;
+pub mod scheduler;
 pub mod side;
+mod stop_book;
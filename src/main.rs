@@ -1,14 +1,12 @@
 //main.rs
-mod order;
-mod order_book;
-mod order_queue;
-mod order_side;
-mod side;
-use order::TimeInForce;
-use order_book::OrderBook;
+use orderbook::{
+    engine::Engine,
+    order::{Order, OrderType, TimeInForce},
+    side,
+};
 use std::{
     env, thread,
-    {collections::BTreeMap, error::Error, process},
+    {error::Error, process},
     {io, sync::mpsc},
 };
 
@@ -44,7 +42,8 @@ fn run() -> Result<(), Box<dyn Error>> {
 
     thread::spawn(move || {
         let mut amount_of_flushes = 0;
-        let mut order_books: BTreeMap<String, OrderBook> = BTreeMap::new();
+        // one order book per symbol, each sending to its own producer handle
+        let mut engine = Engine::new(false, Some(tx));
         // Build the CSV reader and iterate over each record.
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
@@ -86,52 +85,62 @@ fn run() -> Result<(), Box<dyn Error>> {
                             user_order_id: record.get(6).unwrap().parse::<u32>().unwrap(),
                         }
                     };
-                    let trading_enabled = if amount_of_flushes >= 10 { true } else { false };
-                    let order_book =
-                        order_books
-                            .entry(transaction.symbol.clone())
-                            .or_insert(OrderBook::new(
-                                transaction.symbol.clone(),
-                                trading_enabled,
-                                Some(tx.clone()), // creating multiple producer threads - one for each symbol
-                            ));
-                    if transaction.price > 0 {
-                        order_book.add_limit_order(
-                            transaction.side,
-                            transaction.quantity,
-                            transaction.price,
-                            transaction.user_id,
+                    let order = if transaction.price > 0 {
+                        Order::new(
                             transaction.user_order_id,
+                            transaction.user_id,
+                            transaction.side,
+                            OrderType::Limit,
                             TimeInForce::GTC,
-                        );
+                            transaction.price,
+                            transaction.quantity,
+                        )
                     } else {
-                        order_book.add_market_order(
+                        Order::new(
+                            transaction.user_order_id,
+                            transaction.user_id,
                             transaction.side,
+                            OrderType::Market,
+                            TimeInForce::IOC,
+                            0,
                             transaction.quantity,
-                            transaction.user_id,
-                            transaction.user_order_id,
-                        );
-                    }
+                        )
+                    };
+                    // the CSV names orders by the user's own id and the expected
+                    // output echoes it back, `submit_order` would report engine ids
+                    engine.add_order(&transaction.symbol, order);
                 }
                 Some("C") => {
-                    // TODO: implement better cancel logic
-                    if ignore_errors {
-                        let user_id = record.get(1).unwrap_or("").parse::<u32>().unwrap_or(0);
-                        let order_id = record.get(2).unwrap_or("").parse::<u32>().unwrap_or(0);
-                        for (_, order_book) in order_books.iter_mut() {
-                            order_book.cancel_order_user(user_id, order_id);
-                        }
+                    let (user_id, order_id) = if ignore_errors {
+                        (
+                            record.get(1).unwrap_or("").parse::<u32>().unwrap_or(0),
+                            record.get(2).unwrap_or("").parse::<u32>().unwrap_or(0),
+                        )
                     } else {
-                        let user_id = record.get(1).unwrap().parse::<u32>().unwrap();
-                        let order_id = record.get(2).unwrap().parse::<u32>().unwrap();
-                        for (_, order_book) in order_books.iter_mut() {
-                            order_book.cancel_order_user(user_id, order_id);
-                        }
+                        (
+                            record.get(1).unwrap().parse::<u32>().unwrap(),
+                            record.get(2).unwrap().parse::<u32>().unwrap(),
+                        )
+                    };
+                    engine.cancel_order(user_id, order_id);
+                }
+                // kill switch, disables a user and cancels all of their orders
+                Some("K") | Some("U") => {
+                    let user_id = if ignore_errors {
+                        record.get(1).unwrap_or("").parse::<u32>().unwrap_or(0)
+                    } else {
+                        record.get(1).unwrap().parse::<u32>().unwrap()
+                    };
+                    if record.get(0) == Some("K") {
+                        engine.disable_user(user_id);
+                    } else {
+                        engine.enable_user(user_id);
                     }
                 }
                 Some("F") => {
                     amount_of_flushes += 1;
-                    order_books.clear();
+                    engine.flush();
+                    engine.set_trading_enabled(amount_of_flushes >= 10);
                 }
                 _ => {
                     if ignore_errors {
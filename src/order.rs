@@ -1,11 +1,16 @@
 use crate::side::Side;
 use chrono::{DateTime, Utc};
-use std::usize;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderType {
     Limit,
     Market,
+    // market order whose unfilled rest becomes a limit order
+    MarketToLimit,
+    Iceberg,
+    StopMarket,
+    StopLimit,
+    TrailingStop,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,7 +21,65 @@ pub enum TimeInForce {
     FOK, // Fill or kill
 }
 
-#[derive(Debug, Clone)]
+// what to do with a post only order that would cross the book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostOnly {
+    Reject,
+    Slide,
+}
+
+// how far a trailing stop trails behind the best price seen so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailingOffset {
+    Fixed(usize),
+    // percent of the best price, above 0 and below 100
+    Percent(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrailingStop {
+    pub offset: TrailingOffset,
+    // best trade price seen since submission, the highest one for sell stops
+    // and the lowest one for buy stops
+    pub watermark: usize,
+    // released as a limit order this far through the trigger price when set,
+    // otherwise as a market order
+    pub limit_offset: Option<usize>,
+}
+
+impl TrailingStop {
+    pub fn stop_price(&self, side: Side) -> usize {
+        let offset = match self.offset {
+            TrailingOffset::Fixed(offset) => offset,
+            TrailingOffset::Percent(percent) => {
+                (self.watermark as f64 * percent / 100.0).round() as usize
+            }
+        };
+        match side {
+            Side::Buy => self.watermark.saturating_add(offset),
+            Side::Sell => self.watermark.saturating_sub(offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PegType {
+    // best price on the order's own side
+    Primary,
+    // best price on the opposite side
+    Market,
+    // middle of the best bid and offer
+    Midpoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peg {
+    pub peg_type: PegType,
+    // added to the reference price, negative values move the order down
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Order {
     pub id: u32,
     pub user_id: u32,
@@ -27,6 +90,29 @@ pub struct Order {
     pub quantity: usize,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    // only used by GTD orders
+    pub expires_at: Option<DateTime<Utc>>,
+    // only used by iceberg orders, `quantity` is the visible slice while
+    // the rest waits in `reserve_quantity` until the slice is filled
+    pub peak_quantity: usize,
+    pub reserve_quantity: usize,
+    // only used by stop orders, they wait for a trade at or through this price
+    pub stop_price: usize,
+    pub post_only: Option<PostOnly>,
+    pub trailing_stop: Option<TrailingStop>,
+    pub peg: Option<Peg>,
+    // the other leg of a one-cancels-other pair
+    pub linked_id: Option<u32>,
+    // smallest quantity the order may trade on arrival, 0 when unset
+    pub min_quantity: usize,
+    // takes part in matching without being displayed
+    pub hidden: bool,
+    // may only ever be executed in full
+    pub all_or_none: bool,
+    // entry order of a bracket this order belongs to
+    pub parent_id: Option<u32>,
+    // ClOrdID the user gave the order, unique among their open orders
+    pub client_order_id: Option<String>,
 }
 
 impl Order {
@@ -49,8 +135,28 @@ impl Order {
             quantity,
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            expires_at: None,
+            peak_quantity: 0,
+            reserve_quantity: 0,
+            stop_price: 0,
+            post_only: None,
+            trailing_stop: None,
+            peg: None,
+            linked_id: None,
+            min_quantity: 0,
+            hidden: false,
+            all_or_none: false,
+            parent_id: None,
+            client_order_id: None,
         }
     }
+
+    pub fn is_stop(&self) -> bool {
+        matches!(
+            self.order_type,
+            OrderType::StopMarket | OrderType::StopLimit | OrderType::TrailingStop
+        )
+    }
 }
 
 #[cfg(test)]
@@ -74,6 +180,8 @@ mod tests {
         assert_eq!(order.time_in_force, TimeInForce::GTC);
         assert_eq!(order.price, 100);
         assert_eq!(order.quantity, 100);
+        assert_eq!(order.expires_at, None);
+        assert_eq!(order.reserve_quantity, 0);
     }
 
     #[test]
@@ -101,4 +209,19 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_trailing_stop_price() {
+        let mut trailing_stop = TrailingStop {
+            offset: TrailingOffset::Fixed(5),
+            watermark: 100,
+            limit_offset: None,
+        };
+        assert_eq!(trailing_stop.stop_price(Side::Sell), 95);
+        assert_eq!(trailing_stop.stop_price(Side::Buy), 105);
+        trailing_stop.offset = TrailingOffset::Percent(2.5);
+        trailing_stop.watermark = 200;
+        assert_eq!(trailing_stop.stop_price(Side::Sell), 195);
+        assert_eq!(trailing_stop.stop_price(Side::Buy), 205);
+    }
 }
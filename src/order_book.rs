@@ -1,45 +1,245 @@
 // order_book.rs
 use crate::{
-    order::{Order, OrderType, TimeInForce},
+    clock::{Clock, SystemClock},
+    order::{Order, OrderType, Peg, PegType, PostOnly, TimeInForce, TrailingOffset, TrailingStop},
     order_side::OrderSide,
+    scale_order::ScaleOrder,
     side::Side,
+    stop_book::StopBook,
 };
-use std::{collections::BTreeMap, sync::mpsc::Sender};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+};
+
+// how many done orders keep their fill and ClOrdID records around
+const DONE_ORDERS_KEPT: usize = 10_000;
 
 #[derive(Debug)]
 pub struct InProcessOrder {
-    done: Vec<Order>,
-    partial: Option<Order>,
-    partial_quantity_processed: usize,
-    quantity_left: usize,
-    err: Option<String>,
+    pub done: Vec<Order>,
+    pub partial: Option<Order>,
+    pub partial_quantity_processed: usize,
+    pub quantity_left: usize,
+    // quantity that was dropped instead of resting in the book (e.g. IOC remainder)
+    pub cancelled_quantity: usize,
+    pub err: Option<String>,
+}
+
+impl InProcessOrder {
+    pub(crate) fn new(quantity_left: usize) -> Self {
+        Self {
+            done: Vec::new(),
+            partial: None,
+            partial_quantity_processed: 0,
+            quantity_left,
+            cancelled_quantity: 0,
+            err: None,
+        }
+    }
+}
+
+// (price, volume) of the displayed levels on each side of the book
+#[derive(Debug, Clone, PartialEq)]
+pub struct Depth {
+    pub bids: Vec<(usize, usize)>,
+    pub asks: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CancelResult {
+    Cancelled(Order),
+    UnknownOrder,
+    NotOwner,
+    AlreadyFilled,
+}
+
+// an order as its owner refers to it, by engine id or by ClOrdID
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderRef {
+    Id(u32),
+    ClientId(String),
+}
+
+impl From<u32> for OrderRef {
+    fn from(id: u32) -> Self {
+        OrderRef::Id(id)
+    }
+}
+
+impl From<&str> for OrderRef {
+    fn from(client_order_id: &str) -> Self {
+        OrderRef::ClientId(client_order_id.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderStatus {
+    Open(Order),
+    Filled,
+    Unknown,
+}
+
+// two sided quote of a market maker, a size of 0 leaves that side empty
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub user_id: u32,
+    pub bid_price: usize,
+    pub bid_size: usize,
+    pub ask_price: usize,
+    pub ask_size: usize,
+}
+
+// how far a market order may sweep away from the best price it found on arrival
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceProtection {
+    Ticks(usize),
+    Percent(f64),
 }
 
 #[derive(Debug)]
 pub struct OrderBook {
     orders: BTreeMap<u32, Order>,
+    // ids of the open orders of every user
+    user_orders: BTreeMap<u32, BTreeSet<u32>>,
     bids: OrderSide,
     asks: OrderSide,
+    stops: StopBook,
+    // ids of pegged orders in submission order
+    pegged: Vec<u32>,
+    // owner and child order ids of every scale order, keyed by the parent id
+    scale_orders: BTreeMap<u32, (u32, Vec<u32>)>,
+    // take profit and stop loss templates of bracket orders, keyed by the entry id
+    brackets: BTreeMap<u32, (Order, Order)>,
+    // entry fills whose bracket children still have to be activated
+    pending_brackets: Vec<(u32, usize)>,
+    // bid and ask ids of the live quote of every market maker, keyed by user
+    quotes: BTreeMap<u32, (u32, u32)>,
+    quoting: bool,
+    // ids of orders that traded in full, to tell them apart from unknown ids
+    filled_orders: BTreeSet<u32>,
+    // owner and ClOrdID of every order submitted with one and the way back,
+    // kept after the order is done so events and lookups still find it
+    client_ids: BTreeMap<u32, (u32, String)>,
+    client_orders: BTreeMap<(u32, String), u32>,
+    // ids of done orders, oldest first, the records of the oldest are dropped
+    // once more than DONE_ORDERS_KEPT are done
+    done_orders: VecDeque<u32>,
+    // ids handed out by the book count down from the top of the range to stay
+    // clear of the ids users pick, books of one engine share the counter so
+    // the ids are unique across symbols
+    next_id: Arc<AtomicU32>,
+    last_trade_price: Option<usize>,
+    triggering_stops: bool,
+    price_protection: Option<PriceProtection>,
     symbol: String,
     trading_enabled: bool,
     tx: Option<Sender<String>>,
+    clock: Box<dyn Clock>,
 }
 
 impl OrderBook {
     pub fn new(symbol: String, trading_enabled: bool, tx: Option<Sender<String>>) -> OrderBook {
         Self {
             orders: BTreeMap::new(),
+            user_orders: BTreeMap::new(),
             bids: OrderSide::new(Side::Buy),
             asks: OrderSide::new(Side::Sell),
+            stops: StopBook::new(),
+            pegged: Vec::new(),
+            scale_orders: BTreeMap::new(),
+            brackets: BTreeMap::new(),
+            pending_brackets: Vec::new(),
+            quotes: BTreeMap::new(),
+            quoting: false,
+            filled_orders: BTreeSet::new(),
+            client_ids: BTreeMap::new(),
+            client_orders: BTreeMap::new(),
+            done_orders: VecDeque::new(),
+            next_id: Arc::new(AtomicU32::new(u32::MAX)),
+            last_trade_price: None,
+            triggering_stops: false,
+            price_protection: None,
             symbol,
             trading_enabled,
             tx,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // hands out ids from `next_id` from now on, e.g. one counter for every book
+    pub fn set_id_counter(&mut self, next_id: Arc<AtomicU32>) {
+        self.next_id = next_id;
+    }
+
+    pub fn set_price_protection(&mut self, price_protection: Option<PriceProtection>) {
+        self.price_protection = price_protection;
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn last_trade_price(&self) -> Option<usize> {
+        self.last_trade_price
+    }
+
+    // displayed price levels, best first, hidden quantity is left out
+    pub fn depth(&self, levels: usize) -> Depth {
+        Depth {
+            bids: self.bids.depth(levels),
+            asks: self.asks.depth(levels),
         }
     }
+
     pub fn flush(&mut self) {
         self.bids.flush();
         self.asks.flush();
+        self.stops.flush();
+        self.pegged.clear();
+        self.scale_orders.clear();
+        self.brackets.clear();
+        self.pending_brackets.clear();
+        self.quotes.clear();
+        self.filled_orders.clear();
+        self.client_ids.clear();
+        self.client_orders.clear();
+        self.done_orders.clear();
         self.orders.clear();
+        self.user_orders.clear();
+        self.last_trade_price = None;
+    }
+
+    // quote legs are acknowledged together by the quote
+    fn acknowledge(&self, user_id: u32, id: u32) {
+        if !self.quoting {
+            self.log(format!(
+                "A, {:?}, {:?}{}",
+                user_id,
+                id,
+                self.client_order_ids(&[id])
+            ));
+        }
+    }
+
+    // turns an order away, `R` carries its ClOrdID when it has one
+    fn reject(&self, order: &mut InProcessOrder, err: &str, user_id: u32, id: u32) {
+        order.err = Some(err.to_string());
+        self.log(format!(
+            "R, {:?}, {:?}{}",
+            user_id,
+            id,
+            self.client_order_ids(&[id])
+        ));
     }
 
     fn log(&self, msg: String) {
@@ -59,304 +259,3955 @@ impl OrderBook {
         id: u32,
         time_in_force: TimeInForce,
     ) -> InProcessOrder {
-        let mut order = InProcessOrder {
-            done: Vec::new(),
-            partial: None,
-            partial_quantity_processed: 0,
-            quantity_left: size,
-            err: None,
-        };
-        if self.orders.contains_key(&id) {
-            order.err = Some("Order Already Exists".to_string());
+        // there is no expiry to go with a GTD order here
+        if time_in_force == TimeInForce::GTD {
+            let mut order = InProcessOrder::new(size);
+            self.reject(&mut order, "Use add_gtd_order for GTD orders", user_id, id);
             return order;
         }
+        self.add_order(Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            time_in_force,
+            price,
+            size,
+        ))
+    }
 
-        let mut quantity_to_trade = size;
-
-        let comparator = match side {
-            Side::Buy => Self::greater_than_or_equal,
-            Side::Sell => Self::lower_than_or_equal,
-        };
-
-        while quantity_to_trade > 0 {
-            {
-                let side_to_process = match side {
-                    Side::Buy => &self.asks,
-                    Side::Sell => &self.bids,
-                };
-                if side_to_process.num_orders <= 0 {
-                    break;
-                }
-                let best_price = match side {
-                    Side::Buy => side_to_process.min_price_queue(),
-                    Side::Sell => side_to_process.max_price_queue(),
-                };
-                if best_price.is_none() {
-                    break;
-                }
-                if !comparator(&price, &best_price.as_ref().unwrap().price) {
-                    break;
-                } else if !self.trading_enabled {
-                    order.err = Some("Trading is not enabled".to_string());
-                    self.log(format!("R, {:?}, {:?}", user_id, id));
-                    return order;
-                }
-                // TODO: implement FOK order logic
-                // If FOK order was not matched completely don't process it
-                // if time_in_force == TimeInForce::FOK && size > available_at_limit_price {
-                //   break;
-                // }
-            }
-            self.log(format!("A, {:?}, {:?}", user_id, id));
-            let process_queue = self.process_queue(side, quantity_to_trade, user_id, id);
-            order.done.extend(process_queue.done);
-            order.partial = process_queue.partial;
-            order.partial_quantity_processed = process_queue.partial_quantity_processed;
-            quantity_to_trade = process_queue.quantity_left;
-            order.quantity_left = quantity_to_trade;
-        }
-
-        if quantity_to_trade > 0 {
-            let new_order = Order::new(
-                id,
-                user_id,
-                side,
-                OrderType::Limit,
-                time_in_force,
-                price,
-                quantity_to_trade,
-            );
-            if order.done.len() > 0 {
-                order.partial_quantity_processed = size - quantity_to_trade;
-                order.partial = Some(new_order.clone());
-            }
+    // limit order that rests until `expires_at`, see `expire_orders`
+    pub fn add_gtd_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        price: usize,
+        user_id: u32,
+        id: u32,
+        expires_at: DateTime<Utc>,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            TimeInForce::GTD,
+            price,
+            size,
+        );
+        order.expires_at = Some(expires_at);
+        self.add_order(order)
+    }
 
-            self.log(format!("A, {:?}, {:?}", user_id, id));
-            let qt = new_order.quantity.clone();
-            let p = new_order.price.clone();
-            if side == Side::Buy {
-                let m_price = self.bids.max_price();
-                let value = self.bids.add_order(new_order);
-                self.orders.insert(id, value);
-                if p > m_price {
-                    self.log(format!("B, B, {:?}, {:?}", p, qt));
-                } else if p == m_price {
-                    let queue = self.bids.max_price_queue();
-                    self.log(format!("B, B, {:?}, {:?}", p, &queue.unwrap().volume));
-                }
-            } else {
-                let m_price = self.asks.min_price();
-                let value = self.asks.add_order(new_order);
-                self.orders.insert(id, value);
-                if p < m_price {
-                    self.log(format!("B, S, {:?}, {:?}", p, qt));
-                } else if p == m_price {
-                    let queue = self.asks.min_price_queue();
-                    self.log(format!("B, S, {:?}, {:?}", p, &queue.unwrap().volume));
-                }
-            }
-        } else {
-            let mut total_quantity: usize = 0;
-            let mut total_price: usize = 0;
-            order.done.iter().for_each(|order| {
-                total_quantity += order.quantity;
-                total_price += order.price * order.quantity;
-            });
+    pub fn add_market_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        self.add_order(Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Market,
+            TimeInForce::IOC,
+            0,
+            size,
+        ))
+    }
 
-            if order.partial_quantity_processed > 0 && order.partial.is_some() {
-                total_quantity += order.partial_quantity_processed;
-                total_price +=
-                    order.partial.as_ref().unwrap().price * order.partial_quantity_processed;
-            }
+    // rests like a limit order but is left out of the displayed volume and the
+    // top of book, it queues behind every displayed order at its price
+    pub fn add_hidden_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        price: usize,
+        user_id: u32,
+        id: u32,
+        time_in_force: TimeInForce,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            time_in_force,
+            price,
+            size,
+        );
+        order.hidden = true;
+        self.add_order(order)
+    }
 
-            order.done.push(Order::new(
-                id,
-                user_id,
-                side,
-                OrderType::Limit,
-                time_in_force,
-                total_price / total_quantity,
-                total_quantity,
-            ));
-        }
+    // only ever trades in full, both on arrival and while resting in the book
+    pub fn add_all_or_none_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        price: usize,
+        user_id: u32,
+        id: u32,
+        time_in_force: TimeInForce,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            time_in_force,
+            price,
+            size,
+        );
+        order.all_or_none = true;
+        self.add_order(order)
+    }
 
-        // TODO: implement IOC order logic
-        // If IOC order was not matched completely remove from the order book
-        // if time_in_force == TimeInForce::IOC && order.quantity_left > 0 {
-        //   self.cancel_order(order.id);
-        // }
+    // sweeps like a market order, the unfilled rest is turned into a limit
+    // order at the last execution price
+    pub fn add_market_to_limit_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        self.add_order(Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::MarketToLimit,
+            TimeInForce::GTC,
+            0,
+            size,
+        ))
+    }
 
-        return order;
+    // only `peak` of the order is displayed at a time, the rest of `size` is
+    // kept in reserve and shown slice by slice as the book trades through it
+    pub fn add_iceberg_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        price: usize,
+        peak: usize,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Iceberg,
+            TimeInForce::GTC,
+            price,
+            size,
+        );
+        order.peak_quantity = peak;
+        self.add_order(order)
     }
 
-    pub fn add_market_order(
+    // post only orders never take liquidity, if they would cross the book they are
+    // either rejected or re-priced one tick behind the touch (`PostOnly::Slide`)
+    pub fn add_post_only_order(
         &mut self,
         side: Side,
         size: usize,
+        price: usize,
         user_id: u32,
         id: u32,
+        post_only: PostOnly,
     ) -> InProcessOrder {
-        let mut in_process_order = InProcessOrder {
-            done: Vec::new(),
-            partial: None,
-            partial_quantity_processed: 0,
-            quantity_left: size,
-            err: None,
-        };
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            price,
+            size,
+        );
+        order.post_only = Some(post_only);
+        self.add_order(order)
+    }
 
-        let mut quantity_to_trade = size;
+    // pegged orders rest at a price derived from the book (see `PegType`) plus
+    // `offset` and are moved automatically whenever that reference changes
+    pub fn add_pegged_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        peg_type: PegType,
+        offset: isize,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            0,
+            size,
+        );
+        order.peg = Some(Peg { peg_type, offset });
+        self.add_order(order)
+    }
 
-        while quantity_to_trade > 0 {
-            {
-                let side_to_process = match side {
-                    Side::Buy => &self.asks,
-                    Side::Sell => &self.bids,
-                };
-                if side_to_process.num_orders <= 0 {
-                    break;
-                }
-                let best_price = match side {
-                    Side::Buy => side_to_process.min_price_queue(),
-                    Side::Sell => side_to_process.max_price_queue(),
-                };
-                if best_price.is_none() {
-                    break;
-                }
+    // entry point for fully described orders, e.g. a GTD order with `expires_at` set
+    pub fn add_order(&mut self, mut new_order: Order) -> InProcessOrder {
+        let now = self.clock.now();
+        new_order.created_at = now;
+        new_order.updated_at = now;
+        let (id, linked_id, size) = (new_order.id, new_order.linked_id, new_order.quantity);
+        if self.reserved_for_bracket(id, new_order.parent_id) {
+            let mut order = InProcessOrder::new(size);
+            order.err = Some("Order Already Exists".to_string());
+            return order;
+        }
+        if !self.orders.contains_key(&id) {
+            self.filled_orders.remove(&id);
+        }
+        let order = match new_order.order_type {
+            OrderType::Limit | OrderType::Iceberg => self.process_limit_order(new_order),
+            OrderType::Market | OrderType::MarketToLimit => self.process_market_order(new_order),
+            OrderType::StopMarket | OrderType::StopLimit | OrderType::TrailingStop => {
+                self.process_stop_order(new_order)
             }
-            self.log(format!("A, {:?}, {:?}", user_id, id));
-            let process_queue = self.process_queue(side, quantity_to_trade, user_id, id);
-            in_process_order.done.extend(process_queue.done);
-            in_process_order.partial = process_queue.partial;
-            in_process_order.partial_quantity_processed = process_queue.partial_quantity_processed;
-            quantity_to_trade = process_queue.quantity_left;
+        };
+        if order.err.is_none() && order.quantity_left < size {
+            self.on_fill(id, linked_id, size - order.quantity_left);
         }
-        in_process_order.quantity_left = quantity_to_trade;
-        return in_process_order;
+        if order.err.is_none() && order.quantity_left == 0 && !self.orders.contains_key(&id) {
+            self.filled_orders.insert(id);
+        }
+        if !self.orders.contains_key(&id) {
+            self.retire_order(id);
+        }
+        self.match_all_or_none();
+        self.activate_brackets();
+        self.trigger_stops();
+        self.reprice_pegs();
+        order
+    }
+
+    // submits `order` under an id assigned by the book, whatever id it carries
+    // is replaced. The user refers to it by `client_order_id` (ClOrdID) from
+    // then on, which must not be used by another of their open orders. No id
+    // is assigned when the ClOrdID is taken
+    pub fn submit_order(
+        &mut self,
+        mut order: Order,
+        client_order_id: &str,
+    ) -> (Option<u32>, InProcessOrder) {
+        if self
+            .live_client_order(order.user_id, client_order_id)
+            .is_some()
+        {
+            let mut in_process_order = InProcessOrder::new(order.quantity);
+            in_process_order.err = Some("Client Order Id Already Exists".to_string());
+            self.log(format!("R, {:?}, -, {}", order.user_id, client_order_id));
+            return (None, in_process_order);
+        }
+        let id = self.next_order_id();
+        order.id = id;
+        order.client_order_id = Some(client_order_id.to_string());
+        self.register_client_order(order.user_id, id, client_order_id);
+        (Some(id), self.add_order(order))
     }
 
-    // pub fn edit_order(&mut self, user_id: u32, id: u32, size: usize) {
-    // TODO: Implement the edit order logic here
-    // }
+    fn register_client_order(&mut self, user_id: u32, id: u32, client_order_id: &str) {
+        self.client_ids
+            .insert(id, (user_id, client_order_id.to_string()));
+        self.client_orders
+            .insert((user_id, client_order_id.to_string()), id);
+    }
 
-    // TODO: redo delete implementation so we use user_id too
-    pub fn cancel_order_user(&mut self, _user_id: u32, id: u32) -> Option<Order> {
-        self.orders.remove(&id).and_then(|order| match order.side {
-            Side::Buy => self.bids.remove_order(&order, &self.tx),
-            Side::Sell => self.asks.remove_order(&order, &self.tx),
-        })
+    // open order of `user_id` that goes by `client_order_id`
+    fn live_client_order(&self, user_id: u32, client_order_id: &str) -> Option<u32> {
+        self.client_orders
+            .get(&(user_id, client_order_id.to_string()))
+            .filter(|id| self.orders.contains_key(id))
+            .copied()
     }
 
-    fn cancel_order(&mut self, id: u32) -> Option<Order> {
-        self.orders.remove(&id).and_then(|order| match order.side {
-            Side::Buy => self.bids.remove_order_internal(&order, &self.tx),
-            Side::Sell => self.asks.remove_order_internal(&order, &self.tx),
-        })
+    // engine id of an order of `user_id`, an unknown ClOrdID comes back as the error
+    fn resolve(&self, user_id: u32, order_ref: OrderRef) -> Result<u32, String> {
+        match order_ref {
+            OrderRef::Id(id) => Ok(id),
+            OrderRef::ClientId(client_order_id) => self
+                .client_orders
+                .get(&(user_id, client_order_id.clone()))
+                .copied()
+                .ok_or(client_order_id),
+        }
     }
 
-    fn greater_than_or_equal(a: &usize, b: &usize) -> bool {
-        return a >= b;
+    // ClOrdIDs echoed at the end of an event, left out when none of the orders has one
+    fn client_order_ids(&self, ids: &[u32]) -> String {
+        let client_ids: Vec<Option<&String>> = ids
+            .iter()
+            .map(|id| self.client_ids.get(id).map(|(_, client_id)| client_id))
+            .collect();
+        Self::format_client_ids(&client_ids)
     }
-    fn lower_than_or_equal(a: &usize, b: &usize) -> bool {
-        return a <= b;
+
+    fn format_client_ids(client_ids: &[Option<&String>]) -> String {
+        if client_ids.iter().all(Option::is_none) {
+            return String::new();
+        }
+        client_ids
+            .iter()
+            .map(|client_id| format!(", {}", client_id.map_or("", |client_id| client_id)))
+            .collect()
     }
 
-    fn process_queue(
+    // submits two orders as a one-cancels-other pair, typically a take profit
+    // limit and a stop. Whatever trades on one leg is taken off the other, once
+    // a leg is done the other one is cancelled, and cancelling either leg
+    // cancels both
+    pub fn add_oco_order(
         &mut self,
-        side: Side,
-        quantity_to_trade: usize,
-        user_id: u32,
-        id: u32,
-    ) -> InProcessOrder {
-        let mut in_process_order = InProcessOrder {
-            done: Vec::new(),
-            partial: None,
-            partial_quantity_processed: 0,
-            quantity_left: quantity_to_trade,
-            err: None,
+        mut first: Order,
+        mut second: Order,
+    ) -> (InProcessOrder, InProcessOrder) {
+        let mut first_order = InProcessOrder::new(first.quantity);
+        let mut second_order = InProcessOrder::new(second.quantity);
+        let err = if first.id == second.id
+            || self.orders.contains_key(&first.id)
+            || self.orders.contains_key(&second.id)
+            || self.reserved_for_bracket(first.id, first.parent_id)
+            || self.reserved_for_bracket(second.id, second.parent_id)
+        {
+            Some("Order Already Exists")
+        } else if first.user_id != second.user_id {
+            Some("Linked orders must belong to the same user")
+        } else {
+            None
         };
+        if let Some(err) = err {
+            first_order.err = Some(err.to_string());
+            second_order.err = Some(err.to_string());
+            return (first_order, second_order);
+        }
 
-        while in_process_order.quantity_left > 0 {
-            if let Some(order_queue) = match side {
-                Side::Buy => self.asks.min_price_queue_mut(),
-                Side::Sell => self.bids.max_price_queue_mut(),
-            } {
-                if order_queue.len() <= 0 {
-                    break;
-                }
-                if let Some(head_order) = order_queue.head() {
-                    let head_size = head_order.quantity;
-                    if in_process_order.quantity_left < head_size {
-                        let mut new_order = Order::new(
-                            head_order.id,
-                            head_order.user_id,
-                            head_order.side,
-                            head_order.order_type,
-                            head_order.time_in_force,
-                            head_order.price,
-                            head_size - in_process_order.quantity_left,
-                        );
-
-                        let msg = format!(
-                            "T, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}",
-                            user_id,
-                            id,
-                            head_order.user_id,
-                            head_order.id,
-                            head_order.price,
-                            in_process_order.quantity_left
-                        );
-
-                        in_process_order.partial = Some(new_order.clone());
-                        self.orders.insert(new_order.id, new_order.clone());
-                        in_process_order.partial_quantity_processed =
-                            in_process_order.quantity_left;
-                        order_queue.update_head(head_order.clone(), new_order.clone());
-                        new_order.quantity = in_process_order.quantity_left;
-                        self.log(msg);
-                        if side == Side::Buy {
-                            let m_price = self.asks.min_price();
-                            self.log(format!(
-                                "B, S, {:?}, {:?}",
-                                m_price,
-                                head_size - in_process_order.quantity_left
-                            ));
-                        } else {
-                            let m_price = self.bids.max_price();
-                            self.log(format!(
-                                "B, B, {:?}, {:?}",
-                                m_price,
-                                head_size - in_process_order.quantity_left
-                            ));
-                        }
-
-                        in_process_order.quantity_left = 0;
-
-                        match new_order.side {
-                            Side::Buy => self.bids.decrease_volume_and_total(&new_order),
-                            Side::Sell => self.asks.decrease_volume_and_total(&new_order),
-                        };
-                    } else {
-                        in_process_order.quantity_left = in_process_order.quantity_left - head_size;
-                        let order_id = head_order.id.clone();
-                        let msg = format!(
-                            "T, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}",
-                            user_id,
-                            id,
-                            head_order.user_id,
-                            head_order.id,
-                            head_order.price,
-                            head_order.quantity
-                        );
-                        self.log(msg);
-                        if let Some(canceled_order) = self.cancel_order(order_id) {
-                            in_process_order.done.push(canceled_order);
-                        };
-                    }
-                }
-            }
+        first.linked_id = Some(second.id);
+        second.linked_id = Some(first.id);
+        let first_id = first.id;
+        let first_size = first.quantity;
+        let first_order = self.add_order(first);
+        if first_order.err.is_some() {
+            self.reject(
+                &mut second_order,
+                "Linked order rejected",
+                second.user_id,
+                second.id,
+            );
+            return (first_order, second_order);
+        }
+        if !self.orders.contains_key(&first_id) {
+            second_order.err = Some("Linked order is done".to_string());
+            return (first_order, second_order);
         }
-        return in_process_order;
+        // the first leg may already have traded on arrival
+        second.quantity -= (first_size - first_order.quantity_left).min(second.quantity);
+        let second_order = self.add_order(second);
+        if second_order.err.is_some() {
+            self.cancel_order_user(self.orders[&first_id].user_id, first_id);
+        }
+        (first_order, second_order)
     }
-}
 
-// TODO add unit tests
+    // submits an entry order with a take profit limit and a stop loss attached.
+    // The children follow the entry's fills, each fill adds its quantity to
+    // them, and once live they behave as a one-cancels-other pair. Cancelling
+    // the entry drops the children of whatever did not fill yet. Their ids are
+    // held for the bracket until the entry is done
+    pub fn add_bracket_order(
+        &mut self,
+        entry: Order,
+        mut take_profit: Order,
+        mut stop_loss: Order,
+    ) -> InProcessOrder {
+        let mut order = InProcessOrder::new(entry.quantity);
+        let ids = [entry.id, take_profit.id, stop_loss.id];
+        let exit_side = match entry.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        let err = if ids[0] == ids[1]
+            || ids[0] == ids[2]
+            || ids[1] == ids[2]
+            || ids
+                .iter()
+                .any(|id| self.orders.contains_key(id) || self.reserved_for_bracket(*id, None))
+        {
+            Some("Order Already Exists")
+        } else if take_profit.user_id != entry.user_id || stop_loss.user_id != entry.user_id {
+            Some("Linked orders must belong to the same user")
+        } else if entry.is_stop() || entry.linked_id.is_some() {
+            Some("Invalid bracket entry order")
+        } else if take_profit.side != exit_side
+            || stop_loss.side != exit_side
+            || take_profit.order_type != OrderType::Limit
+            || !stop_loss.is_stop()
+        {
+            Some("Invalid bracket child orders")
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            order.err = Some(err.to_string());
+            return order;
+        }
+
+        take_profit.parent_id = Some(entry.id);
+        stop_loss.parent_id = Some(entry.id);
+        self.brackets.insert(entry.id, (take_profit, stop_loss));
+        let order = self.add_order(entry);
+        if order.err.is_some() {
+            self.brackets.retain(|id, _| ids[0] != *id);
+        }
+        order
+    }
+
+    // take profit and stop loss ids are held for their bracket until the entry
+    // is done, only the bracket's own children may use them
+    fn reserved_for_bracket(&self, id: u32, parent_id: Option<u32>) -> bool {
+        self.brackets
+            .iter()
+            .any(|(entry_id, (take_profit, stop_loss))| {
+                (take_profit.id == id || stop_loss.id == id) && parent_id != Some(*entry_id)
+            })
+    }
+
+    // replaces the market maker's previous quote with a new bid and ask. Both
+    // legs are validated before either is touched and a single `Q` message
+    // acknowledges them. A leg that stays at the same price is resized in place
+    // and keeps its queue position, a quote with both sizes at 0 pulls it
+    pub fn add_quote(&mut self, quote: Quote) -> (InProcessOrder, InProcessOrder) {
+        let mut bid = InProcessOrder::new(quote.bid_size);
+        let mut ask = InProcessOrder::new(quote.ask_size);
+        let crosses = |price: usize, best_price: Option<usize>, side: Side| match (side, best_price)
+        {
+            (Side::Buy, Some(best_price)) => price >= best_price,
+            (Side::Sell, Some(best_price)) => price <= best_price,
+            _ => false,
+        };
+        // the market maker's own legs are replaced, so they never count as the other side
+        let (old_bid_id, old_ask_id) = self.quotes.get(&quote.user_id).copied().unzip();
+        let best_price = |order_side: &OrderSide, own_id: Option<u32>| {
+            let mut level_price = None;
+            while let Some(order_queue) = order_side.next_price_queue(level_price) {
+                if order_queue.iter().any(|order| Some(order.id) != own_id) {
+                    return Some(order_queue.price);
+                }
+                level_price = Some(order_queue.price);
+            }
+            None
+        };
+        let err = if (quote.bid_size > 0 && quote.bid_price == 0)
+            || (quote.ask_size > 0 && quote.ask_price == 0)
+        {
+            Some("Invalid quote price")
+        } else if quote.bid_size > 0 && quote.ask_size > 0 && quote.bid_price >= quote.ask_price {
+            Some("Quote bid must be below its ask")
+        } else if !self.trading_enabled
+            && ((quote.bid_size > 0
+                && crosses(
+                    quote.bid_price,
+                    best_price(&self.asks, old_ask_id),
+                    Side::Buy,
+                ))
+                || (quote.ask_size > 0
+                    && crosses(
+                        quote.ask_price,
+                        best_price(&self.bids, old_bid_id),
+                        Side::Sell,
+                    )))
+        {
+            Some("Trading is not enabled")
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            bid.err = Some(err.to_string());
+            ask.err = Some(err.to_string());
+            return (bid, ask);
+        }
+
+        let (bid_id, ask_id) = match self.quotes.get(&quote.user_id) {
+            Some(ids) => *ids,
+            None => (self.next_order_id(), self.next_order_id()),
+        };
+        self.quotes.insert(quote.user_id, (bid_id, ask_id));
+        self.quoting = true;
+        // both old legs are out of the way before either new one is placed, so
+        // the new bid cannot trade with the old ask or the other way round
+        let bid_kept = self.reduce_quote_leg(Side::Buy, quote.bid_price, quote.bid_size, bid_id);
+        let ask_kept = self.reduce_quote_leg(Side::Sell, quote.ask_price, quote.ask_size, ask_id);
+        let bid = self.place_quote_leg(
+            Side::Buy,
+            quote.bid_price,
+            quote.bid_size,
+            quote.user_id,
+            bid_id,
+            bid_kept,
+        );
+        let ask = self.place_quote_leg(
+            Side::Sell,
+            quote.ask_price,
+            quote.ask_size,
+            quote.user_id,
+            ask_id,
+            ask_kept,
+        );
+        self.quoting = false;
+        self.log(format!(
+            "Q, {:?}, {:?}, {:?}",
+            quote.user_id, bid_id, ask_id
+        ));
+        if quote.bid_size == 0 && quote.ask_size == 0 {
+            self.quotes.remove(&quote.user_id);
+        }
+        (bid, ask)
+    }
+
+    // a leg only shrinking at the same price keeps its place in the queue,
+    // anything else takes it out of the book. Returns whether the leg was kept
+    fn reduce_quote_leg(&mut self, side: Side, price: usize, size: usize, id: u32) -> bool {
+        let old_order = match self.orders.get(&id).cloned() {
+            Some(old_order) => old_order,
+            None => return false,
+        };
+        if size > 0 && old_order.price == price && size <= old_order.quantity {
+            if size < old_order.quantity {
+                let reduced_order = match side {
+                    Side::Buy => self
+                        .bids
+                        .reduce_order(&old_order, old_order.quantity - size),
+                    Side::Sell => self
+                        .asks
+                        .reduce_order(&old_order, old_order.quantity - size),
+                };
+                if let Some(reduced_order) = reduced_order {
+                    self.index_order(reduced_order);
+                }
+                self.log_top_of_book(side);
+            }
+            return true;
+        }
+        self.cancel_order(id);
+        if size == 0 {
+            self.retire_order(id);
+        }
+        false
+    }
+
+    fn place_quote_leg(
+        &mut self,
+        side: Side,
+        price: usize,
+        size: usize,
+        user_id: u32,
+        id: u32,
+        kept: bool,
+    ) -> InProcessOrder {
+        if kept || size == 0 {
+            return InProcessOrder::new(size);
+        }
+        self.add_limit_order(side, size, price, user_id, id, TimeInForce::GTC)
+    }
+
+    // places a ladder of limit orders in one go, either every child order is
+    // submitted or none is. Returns the parent id the ladder is managed by
+    pub fn add_scale_order(&mut self, scale_order: ScaleOrder) -> Result<u32, String> {
+        let ladder = self.validate_scale_order(&scale_order)?;
+        let parent_id = self.next_order_id();
+        self.scale_orders
+            .insert(parent_id, (scale_order.user_id, Vec::new()));
+        self.place_scale_order(parent_id, &scale_order, ladder);
+        Ok(parent_id)
+    }
+
+    // live child orders of a scale order, from start to end price
+    pub fn scale_order(&self, parent_id: u32) -> Option<Vec<Order>> {
+        let (_, children) = self.scale_orders.get(&parent_id)?;
+        Some(
+            children
+                .iter()
+                .filter_map(|id| self.orders.get(id).cloned())
+                .collect(),
+        )
+    }
+
+    pub fn cancel_scale_order(&mut self, user_id: u32, parent_id: u32) -> Vec<Order> {
+        let live_children: Vec<u32> = match self.scale_orders.get(&parent_id) {
+            Some((owner, children)) if *owner == user_id => children
+                .iter()
+                .filter(|id| self.orders.contains_key(id))
+                .copied()
+                .collect(),
+            _ => return Vec::new(),
+        };
+        self.scale_orders.remove(&parent_id);
+        live_children
+            .into_iter()
+            .filter_map(|id| match self.cancel_order_user(user_id, id) {
+                CancelResult::Cancelled(order) => Some(order),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // swaps whatever is left of a scale order for a new ladder under the same
+    // parent id, the old ladder stays untouched if the new one is invalid
+    pub fn amend_scale_order(
+        &mut self,
+        parent_id: u32,
+        scale_order: ScaleOrder,
+    ) -> Result<u32, String> {
+        match self.scale_orders.get(&parent_id) {
+            None => return Err("Scale order not found".to_string()),
+            Some((owner, _)) if *owner != scale_order.user_id => {
+                return Err("Scale order belongs to another user".to_string())
+            }
+            Some(_) => {}
+        }
+        let ladder = self.validate_scale_order(&scale_order)?;
+        self.cancel_scale_order(scale_order.user_id, parent_id);
+        self.scale_orders
+            .insert(parent_id, (scale_order.user_id, Vec::new()));
+        self.place_scale_order(parent_id, &scale_order, ladder);
+        Ok(parent_id)
+    }
+
+    fn validate_scale_order(
+        &self,
+        scale_order: &ScaleOrder,
+    ) -> Result<Vec<(usize, usize)>, String> {
+        let ladder = scale_order.ladder()?;
+        if scale_order.time_in_force != TimeInForce::GTC {
+            return Err("Scale orders must be GTC".to_string());
+        }
+        // a child that crosses would be rejected on its own while trading is off
+        let crosses = match scale_order.side {
+            Side::Buy => self
+                .asks
+                .min_price_queue()
+                .is_some_and(|queue| ladder.iter().any(|(price, _)| *price >= queue.price)),
+            Side::Sell => self
+                .bids
+                .max_price_queue()
+                .is_some_and(|queue| ladder.iter().any(|(price, _)| *price <= queue.price)),
+        };
+        if crosses && !self.trading_enabled {
+            return Err("Trading is not enabled".to_string());
+        }
+        Ok(ladder)
+    }
+
+    fn place_scale_order(
+        &mut self,
+        parent_id: u32,
+        scale_order: &ScaleOrder,
+        ladder: Vec<(usize, usize)>,
+    ) {
+        for (price, quantity) in ladder {
+            let id = self.next_order_id();
+            self.scale_orders.get_mut(&parent_id).unwrap().1.push(id);
+            self.add_limit_order(
+                scale_order.side,
+                quantity,
+                price,
+                scale_order.user_id,
+                id,
+                scale_order.time_in_force,
+            );
+        }
+    }
+
+    pub(crate) fn next_order_id(&mut self) -> u32 {
+        loop {
+            let id = self.next_id.fetch_sub(1, Ordering::Relaxed);
+            if !self.orders.contains_key(&id)
+                && !self.scale_orders.contains_key(&id)
+                && !self.filled_orders.contains(&id)
+                && !self.client_ids.contains_key(&id)
+                && !self.reserved_for_bracket(id, None)
+            {
+                return id;
+            }
+        }
+    }
+
+    // stop orders are parked in the stop book until a trade prints at or through
+    // `stop_price`, then they are released as a market order or, when a
+    // `limit_price` is given, as a limit order
+    pub fn add_stop_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        stop_price: usize,
+        limit_price: Option<usize>,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        let order_type = match limit_price {
+            Some(_) => OrderType::StopLimit,
+            None => OrderType::StopMarket,
+        };
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            order_type,
+            TimeInForce::GTC,
+            limit_price.unwrap_or(0),
+            size,
+        );
+        order.stop_price = stop_price;
+        self.add_order(order)
+    }
+
+    // trailing stops follow the best trade price seen since submission by `offset`
+    // and trigger once the market turns back through that distance
+    pub fn add_trailing_stop_order(
+        &mut self,
+        side: Side,
+        size: usize,
+        offset: TrailingOffset,
+        limit_offset: Option<usize>,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            OrderType::TrailingStop,
+            TimeInForce::GTC,
+            0,
+            size,
+        );
+        order.trailing_stop = Some(TrailingStop {
+            offset,
+            watermark: 0,
+            limit_offset,
+        });
+        self.add_order(order)
+    }
+
+    fn process_stop_order(&mut self, mut new_order: Order) -> InProcessOrder {
+        let mut order = InProcessOrder::new(new_order.quantity);
+        if self.orders.contains_key(&new_order.id) {
+            order.err = Some("Order Already Exists".to_string());
+            return order;
+        }
+        if let Some(trailing_stop) = new_order.trailing_stop.as_mut() {
+            // a percent of 100 or more would put a sell stop at 0
+            let err = match (trailing_stop.offset, self.last_trade_price) {
+                (TrailingOffset::Percent(percent), _)
+                    if !(percent.is_finite() && percent > 0.0 && percent < 100.0) =>
+                {
+                    Some("Invalid trailing percent")
+                }
+                (_, None) => Some("No last trade for trailing stop"),
+                // the trail starts from the last trade
+                (_, Some(last_trade_price)) => {
+                    trailing_stop.watermark = last_trade_price;
+                    new_order.stop_price = trailing_stop.stop_price(new_order.side);
+                    None
+                }
+            };
+            if let Some(err) = err {
+                self.reject(&mut order, err, new_order.user_id, new_order.id);
+                return order;
+            }
+        }
+        if new_order.stop_price == 0 {
+            self.reject(
+                &mut order,
+                "Invalid stop price",
+                new_order.user_id,
+                new_order.id,
+            );
+            return order;
+        }
+        self.log(format!(
+            "A, {:?}, {:?}{}",
+            new_order.user_id,
+            new_order.id,
+            self.client_order_ids(&[new_order.id])
+        ));
+        self.index_order(new_order.clone());
+        self.stops.add_order(new_order);
+        order
+    }
+
+    // releases the stops triggered by the last trade price. Released stops can trade
+    // and trigger further stops, those are picked up by the same loop, so a cascade
+    // resolves batch by batch in the order the stop book hands them out
+    fn trigger_stops(&mut self) {
+        if self.triggering_stops {
+            return;
+        }
+        self.triggering_stops = true;
+        while let Some(last_trade_price) = self.last_trade_price {
+            let triggered = self.stops.take_triggered(last_trade_price);
+            if triggered.is_empty() {
+                break;
+            }
+            for mut order in triggered {
+                self.unindex_order(order.id);
+                self.log(format!(
+                    "S, {:?}, {:?}, {:?}{}",
+                    order.user_id,
+                    order.id,
+                    order.stop_price,
+                    self.client_order_ids(&[order.id])
+                ));
+                let limit_offset = order.trailing_stop.and_then(|t| t.limit_offset);
+                order.order_type = match (order.order_type, limit_offset) {
+                    (OrderType::StopLimit, _) => OrderType::Limit,
+                    (OrderType::TrailingStop, Some(limit_offset)) => {
+                        order.price = match order.side {
+                            Side::Buy => order.stop_price.saturating_add(limit_offset),
+                            Side::Sell => order.stop_price.saturating_sub(limit_offset),
+                        };
+                        OrderType::Limit
+                    }
+                    _ => OrderType::Market,
+                };
+                self.add_order(order);
+            }
+        }
+        self.triggering_stops = false;
+    }
+
+    fn process_limit_order(&mut self, mut new_order: Order) -> InProcessOrder {
+        let side = new_order.side;
+        let size = new_order.quantity;
+        let user_id = new_order.user_id;
+        let id = new_order.id;
+        let time_in_force = new_order.time_in_force;
+
+        let mut order = InProcessOrder::new(size);
+        if self.orders.contains_key(&id) {
+            order.err = Some("Order Already Exists".to_string());
+            return order;
+        }
+
+        if let Err(err) = self.validate_limit_order(&mut new_order) {
+            self.reject(&mut order, err, user_id, id);
+            return order;
+        }
+        let price = new_order.price;
+        let comparator = match side {
+            Side::Buy => Self::greater_than_or_equal,
+            Side::Sell => Self::lower_than_or_equal,
+        };
+
+        let mut quantity_to_trade = size;
+        let mut acknowledged = false;
+        // last level matched against, anything left there is all or none
+        // liquidity the order can't fill
+        let mut level_price = None;
+
+        while quantity_to_trade > 0 {
+            {
+                let side_to_process = match side {
+                    Side::Buy => &self.asks,
+                    Side::Sell => &self.bids,
+                };
+                if side_to_process.num_orders == 0 {
+                    break;
+                }
+                level_price = match side_to_process.next_price_queue(level_price) {
+                    Some(order_queue) => Some(order_queue.price),
+                    None => break,
+                };
+                if !comparator(&price, &level_price.unwrap()) {
+                    break;
+                }
+            }
+            if !acknowledged {
+                self.acknowledge(user_id, id);
+                acknowledged = true;
+            }
+            let process_queue =
+                self.process_queue(side, level_price.unwrap(), quantity_to_trade, user_id, id);
+            order.done.extend(process_queue.done);
+            order.partial = process_queue.partial;
+            order.partial_quantity_processed = process_queue.partial_quantity_processed;
+            quantity_to_trade = process_queue.quantity_left;
+            order.quantity_left = quantity_to_trade;
+        }
+
+        if quantity_to_trade > 0 && time_in_force == TimeInForce::IOC {
+            // IOC orders never rest, whatever did not match right away is dropped
+            if !acknowledged {
+                self.acknowledge(user_id, id);
+            }
+            order.cancelled_quantity = quantity_to_trade;
+            self.log(format!(
+                "X, {:?}, {:?}, {:?}, IOC{}",
+                user_id,
+                id,
+                quantity_to_trade,
+                self.client_order_ids(&[id])
+            ));
+        } else if quantity_to_trade > 0 {
+            let mut new_order = new_order;
+            if new_order.order_type == OrderType::Iceberg {
+                new_order.quantity = new_order.peak_quantity.min(quantity_to_trade);
+                new_order.reserve_quantity = quantity_to_trade - new_order.quantity;
+            } else {
+                new_order.quantity = quantity_to_trade;
+            }
+            if !order.done.is_empty() {
+                order.partial_quantity_processed = size - quantity_to_trade;
+                order.partial = Some(new_order.clone());
+            }
+
+            // acknowledged again after trading, unless all it met was all or
+            // none liquidity it couldn't fill
+            if !acknowledged || quantity_to_trade < size {
+                self.acknowledge(user_id, id);
+            }
+            if new_order.peg.is_some() {
+                self.pegged.push(id);
+            }
+            self.rest_order(new_order);
+        } else {
+            let mut total_quantity: usize = 0;
+            let mut total_price: usize = 0;
+            order.done.iter().for_each(|order| {
+                total_quantity += order.quantity;
+                total_price += order.price * order.quantity;
+            });
+
+            if let Some(partial) = order.partial.as_ref() {
+                if order.partial_quantity_processed > 0 {
+                    total_quantity += order.partial_quantity_processed;
+                    total_price += partial.price * order.partial_quantity_processed;
+                }
+            }
+
+            order.done.push(Order::new(
+                id,
+                user_id,
+                side,
+                OrderType::Limit,
+                time_in_force,
+                total_price / total_quantity,
+                total_quantity,
+            ));
+        }
+
+        order
+    }
+
+    // every reason to turn a limit order away before it touches the book, so an
+    // amend can check its new order while the old one still rests. Sets the
+    // price of pegged and sliding post only orders
+    fn validate_limit_order(&self, new_order: &mut Order) -> Result<(), &'static str> {
+        let side = new_order.side;
+        let size = new_order.quantity;
+        let time_in_force = new_order.time_in_force;
+        if new_order.order_type == OrderType::Iceberg && new_order.peak_quantity == 0 {
+            return Err("Invalid peak quantity");
+        }
+        if new_order.order_type == OrderType::Iceberg && new_order.hidden {
+            return Err("Hidden orders cannot be icebergs");
+        }
+        if time_in_force == TimeInForce::GTD {
+            match new_order.expires_at {
+                None => return Err("Missing expiry for GTD order"),
+                Some(expires_at) if expires_at <= new_order.created_at => {
+                    return Err("Order already expired")
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(peg) = new_order.peg {
+            new_order.price = self
+                .peg_price(side, peg)
+                .ok_or("No reference price for pegged order")?;
+        }
+
+        let comparator = match side {
+            Side::Buy => Self::greater_than_or_equal,
+            Side::Sell => Self::lower_than_or_equal,
+        };
+        if let Some(post_only) = new_order.post_only {
+            let best_price = match side {
+                Side::Buy => self.asks.min_price_queue().map(|queue| queue.price),
+                Side::Sell => self.bids.max_price_queue().map(|queue| queue.price),
+            };
+            if let Some(best_price) = best_price.filter(|best| comparator(&new_order.price, best)) {
+                // slide one tick behind the touch, unless there is no room left for it
+                let slide_price = match side {
+                    Side::Buy => best_price.checked_sub(1).filter(|p| *p > 0),
+                    Side::Sell => best_price.checked_add(1),
+                };
+                match (post_only, slide_price) {
+                    (PostOnly::Slide, Some(slide_price)) => new_order.price = slide_price,
+                    _ => return Err("Post only order would take liquidity"),
+                }
+            }
+        }
+        let price = new_order.price;
+
+        if new_order.all_or_none && new_order.order_type == OrderType::Iceberg {
+            return Err("All or none orders cannot be icebergs");
+        }
+        if new_order.min_quantity > size {
+            return Err("Invalid minimum quantity");
+        }
+        if time_in_force == TimeInForce::FOK {
+            // check the whole depth up front so a FOK order either fills
+            // completely or leaves the book untouched
+            let available_at_limit_price = match side {
+                Side::Buy => self.asks.available_volume(price, size),
+                Side::Sell => self.bids.available_volume(price, size),
+            };
+            if available_at_limit_price < size {
+                return Err("Fill or kill order cannot be filled");
+            }
+        }
+
+        // a min quantity order only trades on arrival if what it can take right
+        // away adds up to its min quantity, otherwise it doesn't trade at all
+        // and an all or none order is a min quantity order for its whole size.
+        // One that can't trade must not rest across the other side either
+        let best_price = match side {
+            Side::Buy => self.asks.next_price_queue(None),
+            Side::Sell => self.bids.next_price_queue(None),
+        }
+        .map(|queue| queue.price);
+        let crosses = best_price.is_some_and(|best_price| comparator(&price, &best_price));
+        let min_quantity = if new_order.all_or_none {
+            size
+        } else {
+            new_order.min_quantity
+        };
+        if min_quantity > 0 {
+            let available_at_limit_price = match side {
+                Side::Buy => self.asks.available_volume(price, min_quantity),
+                Side::Sell => self.bids.available_volume(price, min_quantity),
+            };
+            let rests = time_in_force != TimeInForce::IOC && time_in_force != TimeInForce::FOK;
+            if available_at_limit_price < min_quantity && (crosses || !rests) {
+                return Err(if new_order.all_or_none {
+                    "All or none order cannot be filled"
+                } else {
+                    "Minimum quantity cannot be filled"
+                });
+            }
+        }
+
+        if crosses && !self.trading_enabled {
+            return Err("Trading is not enabled");
+        }
+        Ok(())
+    }
+
+    fn process_market_order(&mut self, new_order: Order) -> InProcessOrder {
+        let side = new_order.side;
+        let size = new_order.quantity;
+        let user_id = new_order.user_id;
+        let id = new_order.id;
+
+        let mut in_process_order = InProcessOrder::new(size);
+        // the remainder of a market to limit order rests under its id
+        if new_order.order_type == OrderType::MarketToLimit && self.orders.contains_key(&id) {
+            in_process_order.err = Some("Order Already Exists".to_string());
+            return in_process_order;
+        }
+
+        let mut quantity_to_trade = size;
+        let mut acknowledged = false;
+        let price_limit = self.protected_price(side);
+        let mut price_limit_reached = false;
+        let mut level_price = None;
+
+        while quantity_to_trade > 0 {
+            {
+                let side_to_process = match side {
+                    Side::Buy => &self.asks,
+                    Side::Sell => &self.bids,
+                };
+                if side_to_process.num_orders == 0 {
+                    break;
+                }
+                let best_price = match side_to_process.next_price_queue(level_price) {
+                    Some(order_queue) => order_queue.price,
+                    None => break,
+                };
+                level_price = Some(best_price);
+                price_limit_reached = match (side, price_limit) {
+                    (Side::Buy, Some(price_limit)) => best_price > price_limit,
+                    (Side::Sell, Some(price_limit)) => best_price < price_limit,
+                    _ => false,
+                };
+                if price_limit_reached {
+                    break;
+                }
+            }
+            if !acknowledged {
+                self.log(format!(
+                    "A, {:?}, {:?}{}",
+                    user_id,
+                    id,
+                    self.client_order_ids(&[id])
+                ));
+                acknowledged = true;
+            }
+            let process_queue =
+                self.process_queue(side, level_price.unwrap(), quantity_to_trade, user_id, id);
+            in_process_order.done.extend(process_queue.done);
+            in_process_order.partial = process_queue.partial;
+            in_process_order.partial_quantity_processed = process_queue.partial_quantity_processed;
+            quantity_to_trade = process_queue.quantity_left;
+        }
+        in_process_order.quantity_left = quantity_to_trade;
+
+        if price_limit_reached && new_order.order_type == OrderType::Market {
+            in_process_order.cancelled_quantity = quantity_to_trade;
+            self.log(format!(
+                "X, {:?}, {:?}, {:?}, PRICE_PROTECTION{}",
+                user_id,
+                id,
+                quantity_to_trade,
+                self.client_order_ids(&[id])
+            ));
+        }
+
+        if quantity_to_trade > 0 && new_order.order_type == OrderType::MarketToLimit {
+            // whatever is left rests as a limit order at the last execution price
+            let last_price = match self.last_trade_price.filter(|_| acknowledged) {
+                Some(last_price) => last_price,
+                None => {
+                    self.reject(
+                        &mut in_process_order,
+                        "No liquidity for market to limit order",
+                        user_id,
+                        id,
+                    );
+                    return in_process_order;
+                }
+            };
+            let mut new_order = new_order;
+            new_order.order_type = OrderType::Limit;
+            new_order.time_in_force = TimeInForce::GTC;
+            new_order.price = last_price;
+            new_order.quantity = quantity_to_trade;
+            in_process_order.partial_quantity_processed = size - quantity_to_trade;
+            in_process_order.partial = Some(new_order.clone());
+            self.log(format!(
+                "A, {:?}, {:?}{}",
+                user_id,
+                id,
+                self.client_order_ids(&[id])
+            ));
+            self.rest_order(new_order);
+        }
+        in_process_order
+    }
+
+    // worst price a market order on `side` may trade at, based on the current
+    // best price of the side it takes from
+    fn protected_price(&self, side: Side) -> Option<usize> {
+        let price_protection = self.price_protection?;
+        let best_price = match side {
+            Side::Buy => self.asks.min_price_queue()?.price,
+            Side::Sell => self.bids.max_price_queue()?.price,
+        };
+        let band = match price_protection {
+            PriceProtection::Ticks(ticks) => ticks,
+            PriceProtection::Percent(percent) => (best_price as f64 * percent / 100.0) as usize,
+        };
+        Some(match side {
+            Side::Buy => best_price.saturating_add(band),
+            Side::Sell => best_price.saturating_sub(band),
+        })
+    }
+
+    // amends the size and price of a resting order. Reducing the size at the
+    // same price keeps the order's place in the queue, anything else sends it to
+    // the back of its new level and it may trade right away. `size` is the new
+    // total size, iceberg reserves included
+    pub fn edit_order(
+        &mut self,
+        user_id: u32,
+        order_ref: impl Into<OrderRef>,
+        size: usize,
+        price: usize,
+    ) -> InProcessOrder {
+        let mut order = InProcessOrder::new(size);
+        let id = match self.resolve(user_id, order_ref.into()) {
+            Ok(id) => id,
+            Err(client_order_id) => {
+                order.err = Some("Order not found".to_string());
+                self.log(format!("R, {:?}, -, {}", user_id, client_order_id));
+                return order;
+            }
+        };
+        let err = match self.orders.get(&id) {
+            None => Some("Order not found"),
+            Some(old_order) if old_order.user_id != user_id => {
+                Some("Order belongs to another user")
+            }
+            Some(old_order) if old_order.is_stop() => Some("Stop orders cannot be amended"),
+            Some(_) if size == 0 || price == 0 => Some("Invalid amend"),
+            Some(_) => None,
+        };
+        if let Some(err) = err {
+            self.reject(&mut order, err, user_id, id);
+            return order;
+        }
+
+        let old_order = self.orders[&id].clone();
+        let old_size = old_order.quantity + old_order.reserve_quantity;
+        if price == old_order.price && size <= old_size {
+            let reduced_order = match old_order.side {
+                Side::Buy => self.bids.reduce_order(&old_order, old_size - size),
+                Side::Sell => self.asks.reduce_order(&old_order, old_size - size),
+            };
+            if let Some(mut reduced_order) = reduced_order {
+                reduced_order.updated_at = self.clock.now();
+                self.index_order(reduced_order);
+            }
+            self.log(format!(
+                "A, {:?}, {:?}{}",
+                user_id,
+                id,
+                self.client_order_ids(&[id])
+            ));
+            let at_top = match old_order.side {
+                Side::Buy => self.bids.displayed_price_queue(),
+                Side::Sell => self.asks.displayed_price_queue(),
+            }
+            .is_some_and(|queue| queue.price == price);
+            if size < old_size && at_top && !old_order.hidden {
+                self.log_top_of_book(old_order.side);
+            }
+            return order;
+        }
+
+        let mut new_order = old_order;
+        new_order.price = price;
+        new_order.quantity = size;
+        new_order.reserve_quantity = 0;
+        new_order.created_at = self.clock.now();
+        // a rejected amend leaves the old order resting untouched
+        if let Err(err) = self.validate_limit_order(&mut new_order.clone()) {
+            self.reject(&mut order, err, user_id, id);
+            return order;
+        }
+        self.cancel_order(id);
+        self.pegged.retain(|pegged_id| *pegged_id != id);
+        self.add_order(new_order)
+    }
+
+    // replaces a resting order with a new one under `new_id`, unlike
+    // `edit_order` the order always gets a new identity and goes to the back
+    // of its level. A `RP` message links the old and the new id
+    pub fn cancel_replace(
+        &mut self,
+        user_id: u32,
+        order_ref: impl Into<OrderRef>,
+        new_order_ref: impl Into<OrderRef>,
+        size: usize,
+        price: usize,
+        time_in_force: TimeInForce,
+    ) -> InProcessOrder {
+        let mut order = InProcessOrder::new(size);
+        let id = match self.resolve(user_id, order_ref.into()) {
+            Ok(id) => id,
+            Err(client_order_id) => {
+                order.err = Some("Order not found".to_string());
+                self.log(format!("R, {:?}, -, {}", user_id, client_order_id));
+                return order;
+            }
+        };
+        // a new ClOrdID gets a new engine id
+        let (new_id, new_client_order_id) = match new_order_ref.into() {
+            OrderRef::Id(new_id) => (new_id, None),
+            OrderRef::ClientId(client_order_id) => (self.next_order_id(), Some(client_order_id)),
+        };
+        let err = match self.orders.get(&id) {
+            None if self.filled_orders.contains(&id) => Some("Order already filled"),
+            None => Some("Order not found"),
+            Some(old_order) if old_order.user_id != user_id => {
+                Some("Order belongs to another user")
+            }
+            Some(old_order) if old_order.is_stop() => Some("Stop orders cannot be replaced"),
+            Some(old_order) if old_order.linked_id.is_some() => {
+                Some("Linked orders cannot be replaced")
+            }
+            Some(_)
+                if self.orders.contains_key(&new_id) || self.reserved_for_bracket(new_id, None) =>
+            {
+                Some("Order Already Exists")
+            }
+            Some(_)
+                if new_client_order_id.as_ref().is_some_and(|client_order_id| {
+                    self.live_client_order(user_id, client_order_id).is_some()
+                }) =>
+            {
+                Some("Client Order Id Already Exists")
+            }
+            Some(_) if size == 0 || price == 0 => Some("Invalid amend"),
+            Some(_) => None,
+        };
+        let new_order = self.orders.get(&id).map(|old_order| {
+            let mut new_order = old_order.clone();
+            new_order.id = new_id;
+            new_order.client_order_id = new_client_order_id.clone();
+            new_order.price = price;
+            new_order.quantity = size;
+            new_order.reserve_quantity = 0;
+            new_order.time_in_force = time_in_force;
+            new_order.peg = None;
+            new_order.created_at = self.clock.now();
+            new_order
+        });
+        // the replacement is checked while the original still rests, a rejected
+        // replace leaves the original untouched
+        let err = err.or_else(|| {
+            let mut new_order = new_order.clone()?;
+            self.validate_limit_order(&mut new_order).err()
+        });
+        if let Some(err) = err {
+            order.err = Some(err.to_string());
+            let client_order_id = match &new_client_order_id {
+                Some(client_order_id) => format!(", {}", client_order_id),
+                None => self.client_order_ids(&[new_id]),
+            };
+            self.log(format!("R, {:?}, {:?}{}", user_id, new_id, client_order_id));
+            return order;
+        }
+        if let Some(client_order_id) = &new_client_order_id {
+            self.register_client_order(user_id, new_id, client_order_id);
+        }
+
+        self.log(format!(
+            "RP, {:?}, {:?}, {:?}{}",
+            user_id,
+            id,
+            new_id,
+            self.client_order_ids(&[id, new_id])
+        ));
+        // the original comes out without a top of book update, the side is
+        // reported once after the replacement is in
+        let new_order = new_order.unwrap();
+        let side = new_order.side;
+        let top_of_book = |book: &Self| {
+            match side {
+                Side::Buy => book.bids.displayed_price_queue(),
+                Side::Sell => book.asks.displayed_price_queue(),
+            }
+            .map(|queue| (queue.price, queue.volume))
+        };
+        let old_top_of_book = top_of_book(self);
+        let old_order = match self.unindex_order(id) {
+            Some(old_order) => old_order,
+            None => return order,
+        };
+        match side {
+            Side::Buy => self.bids.take_order(&old_order),
+            Side::Sell => self.asks.take_order(&old_order),
+        };
+        self.retire_order(id);
+        self.pegged.retain(|pegged_id| *pegged_id != id);
+        if let Some(bracket) = self.brackets.remove(&id) {
+            self.brackets.insert(new_id, bracket);
+        }
+        // a replacement resting at or through the top reports the side itself
+        let reports_top = !new_order.hidden
+            && match (side, top_of_book(self)) {
+                (_, None) => true,
+                (Side::Buy, Some((best_price, _))) => new_order.price >= best_price,
+                (Side::Sell, Some((best_price, _))) => new_order.price <= best_price,
+            };
+        let order = self.add_order(new_order);
+        let rests = self.orders.contains_key(&new_id);
+        if !(rests && reports_top) && top_of_book(self) != old_top_of_book {
+            self.log_top_of_book(side);
+        }
+        order
+    }
+
+    pub fn order(&self, id: u32) -> Option<&Order> {
+        self.orders.get(&id)
+    }
+
+    // order of `user_id` by engine id or ClOrdID
+    pub fn find_order(&self, user_id: u32, order_ref: impl Into<OrderRef>) -> Option<&Order> {
+        let id = self.resolve(user_id, order_ref.into()).ok()?;
+        self.orders
+            .get(&id)
+            .filter(|order| order.user_id == user_id)
+    }
+
+    // whether `user_id` has an order going by `order_ref`, open or filled
+    pub fn knows_order_ref(&self, user_id: u32, order_ref: impl Into<OrderRef>) -> bool {
+        self.resolve(user_id, order_ref.into())
+            .is_ok_and(|id| self.knows_order(id))
+    }
+
+    pub fn order_status(&self, id: u32) -> OrderStatus {
+        match self.orders.get(&id) {
+            Some(order) => OrderStatus::Open(order.clone()),
+            None if self.filled_orders.contains(&id) => OrderStatus::Filled,
+            None => OrderStatus::Unknown,
+        }
+    }
+
+    // open orders of `user_id` by id, stop orders included
+    pub fn open_orders(&self, user_id: u32) -> Vec<&Order> {
+        self.user_orders
+            .get(&user_id)
+            .map(|ids| ids.iter().map(|id| &self.orders[id]).collect())
+            .unwrap_or_default()
+    }
+
+    // orders resting at `price` in queue order, hidden ones included, with
+    // their position in the queue starting at 1
+    pub fn orders_at_price(&self, side: Side, price: usize) -> Vec<(usize, &Order)> {
+        let order_queue = match side {
+            Side::Buy => self.bids.price_queue(price),
+            Side::Sell => self.asks.price_queue(price),
+        };
+        order_queue
+            .map(|queue| {
+                queue
+                    .iter()
+                    .enumerate()
+                    .map(|(index, order)| (index + 1, order))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // whether the order is resting in this book or traded in full here
+    pub fn knows_order(&self, id: u32) -> bool {
+        self.orders.contains_key(&id) || self.filled_orders.contains(&id)
+    }
+
+    // cancels an order of `user_id`, failures are reported with a `CR` message
+    pub fn cancel_order_user(
+        &mut self,
+        user_id: u32,
+        order_ref: impl Into<OrderRef>,
+    ) -> CancelResult {
+        let id = match self.resolve(user_id, order_ref.into()) {
+            Ok(id) => id,
+            Err(client_order_id) => {
+                self.log(format!(
+                    "CR, {:?}, -, UNKNOWN_ORDER, {}",
+                    user_id, client_order_id
+                ));
+                return CancelResult::UnknownOrder;
+            }
+        };
+        let (result, reason) = match self.orders.get(&id) {
+            Some(order) if order.user_id != user_id => (CancelResult::NotOwner, "NOT_OWNER"),
+            Some(_) => match self.cancel_and_acknowledge(id) {
+                Some(order) => (CancelResult::Cancelled(order), ""),
+                None => (CancelResult::UnknownOrder, "UNKNOWN_ORDER"),
+            },
+            None if self.filled_orders.contains(&id) => {
+                (CancelResult::AlreadyFilled, "ALREADY_FILLED")
+            }
+            None => (CancelResult::UnknownOrder, "UNKNOWN_ORDER"),
+        };
+        match &result {
+            CancelResult::Cancelled(order) => {
+                if let Some(linked_id) = order.linked_id {
+                    self.cancel_and_acknowledge(linked_id);
+                }
+                self.reprice_pegs();
+            }
+            _ => self.log(format!(
+                "CR, {:?}, {:?}, {}{}",
+                user_id,
+                id,
+                reason,
+                self.client_order_ids(&[id])
+            )),
+        }
+        result
+    }
+
+    fn cancel_and_acknowledge(&mut self, id: u32) -> Option<Order> {
+        let order = self.unindex_order(id)?;
+        self.brackets.remove(&id);
+        self.retire_order(id);
+        if order.is_stop() {
+            let removed_order = self.stops.remove_order(&order);
+            if removed_order.is_some() {
+                self.log(format!(
+                    "A, {:?}, {:?}{}",
+                    order.user_id,
+                    order.id,
+                    self.client_order_ids(&[order.id])
+                ));
+            }
+            return removed_order;
+        }
+        match order.side {
+            Side::Buy => self.bids.remove_order(&order, &self.tx),
+            Side::Sell => self.asks.remove_order(&order, &self.tx),
+        }
+    }
+
+    // cancels every order of `user_id`, stop orders included
+    pub fn cancel_user_orders(&mut self, user_id: u32) -> Vec<Order> {
+        let ids = self
+            .user_orders
+            .get(&user_id)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        self.mass_cancel(ids)
+    }
+
+    // cancels every order on `side`, stop orders included
+    pub fn cancel_side_orders(&mut self, side: Side) -> Vec<Order> {
+        let ids = self
+            .orders
+            .values()
+            .filter(|order| order.side == side)
+            .map(|order| order.id)
+            .collect();
+        self.mass_cancel(ids)
+    }
+
+    // cancels every order resting on `side` priced from `low` to `high` (inclusive)
+    pub fn cancel_price_range(&mut self, side: Side, low: usize, high: usize) -> Vec<Order> {
+        let ids = self
+            .orders
+            .values()
+            .filter(|order| order.side == side && !order.is_stop())
+            .filter(|order| (low..=high).contains(&order.price))
+            .map(|order| order.id)
+            .collect();
+        self.mass_cancel(ids)
+    }
+
+    // cancels the orders without acknowledging each of them, a single `M`
+    // message sums them up followed by the top of book of the sides that changed
+    fn mass_cancel(&mut self, mut ids: Vec<u32>) -> Vec<Order> {
+        let top_of_book = |order_book: &OrderBook| {
+            (
+                order_book
+                    .bids
+                    .displayed_price_queue()
+                    .map(|queue| (queue.price, queue.volume)),
+                order_book
+                    .asks
+                    .displayed_price_queue()
+                    .map(|queue| (queue.price, queue.volume)),
+            )
+        };
+        let (old_bid, old_ask) = top_of_book(self);
+
+        let mut cancelled_orders = Vec::new();
+        let mut index = 0;
+        while index < ids.len() {
+            let id = ids[index];
+            index += 1;
+            let order = match self.unindex_order(id) {
+                Some(order) => order,
+                None => continue,
+            };
+            self.brackets.remove(&id);
+            self.retire_order(id);
+            let removed_order = if order.is_stop() {
+                self.stops.remove_order(&order)
+            } else {
+                match order.side {
+                    Side::Buy => self.bids.take_order(&order),
+                    Side::Sell => self.asks.take_order(&order),
+                }
+            };
+            if let Some(removed_order) = removed_order {
+                // the other leg of an OCO pair goes with it
+                ids.extend(removed_order.linked_id);
+                cancelled_orders.push(removed_order);
+            }
+        }
+
+        self.log(format!("M, {:?}", cancelled_orders.len()));
+        let (new_bid, new_ask) = top_of_book(self);
+        if new_bid != old_bid {
+            self.log_top_of_book(Side::Buy);
+        }
+        if new_ask != old_ask {
+            self.log_top_of_book(Side::Sell);
+        }
+        self.reprice_pegs();
+        cancelled_orders
+    }
+
+    // removes every GTD order that has expired by the clock's current time
+    pub fn expire_orders(&mut self) -> Vec<Order> {
+        let now = self.clock.now();
+        let expired: Vec<u32> = self
+            .orders
+            .values()
+            .filter(|order| order.time_in_force == TimeInForce::GTD)
+            .filter(|order| matches!(order.expires_at, Some(expires_at) if expires_at <= now))
+            .map(|order| order.id)
+            .collect();
+
+        let mut expired_orders = Vec::new();
+        for id in expired {
+            if let Some(order) = self.orders.get(&id) {
+                self.log(format!(
+                    "E, {:?}, {:?}{}",
+                    order.user_id,
+                    order.id,
+                    self.client_order_ids(&[order.id])
+                ));
+            }
+            if let Some(order) = self.withdraw_order(id) {
+                expired_orders.push(order);
+            }
+        }
+        self.reprice_pegs();
+        expired_orders
+    }
+
+    // takes an order out on the book's own account, e.g. when it expires. The
+    // other leg of an OCO pair goes with it like on a user cancel
+    fn withdraw_order(&mut self, id: u32) -> Option<Order> {
+        let order = self.cancel_order(id)?;
+        self.brackets.remove(&id);
+        self.retire_order(id);
+        if let Some(linked_id) = order.linked_id {
+            self.cancel_and_acknowledge(linked_id);
+        }
+        Some(order)
+    }
+
+    fn cancel_order(&mut self, id: u32) -> Option<Order> {
+        if self.orders.get(&id)?.is_stop() {
+            let order = self.unindex_order(id)?;
+            return self.stops.remove_order(&order);
+        }
+        self.unindex_order(id).and_then(|order| match order.side {
+            Side::Buy => self.bids.remove_order_internal(&order, &self.tx),
+            Side::Sell => self.asks.remove_order_internal(&order, &self.tx),
+        })
+    }
+
+    // adding and removing `orders` goes through these two to keep `user_orders`
+    // in step, updating an order in place does not touch the index
+    fn index_order(&mut self, order: Order) {
+        self.user_orders
+            .entry(order.user_id)
+            .or_default()
+            .insert(order.id);
+        self.orders.insert(order.id, order);
+    }
+
+    fn unindex_order(&mut self, id: u32) -> Option<Order> {
+        let order = self.orders.remove(&id)?;
+        if let Some(ids) = self.user_orders.get_mut(&order.user_id) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.user_orders.remove(&order.user_id);
+            }
+        }
+        Some(order)
+    }
+
+    // queues the fill and ClOrdID records of a filled, cancelled or expired
+    // order for removal and drops those of the oldest done orders. An id that
+    // is back in the book by then keeps them
+    fn retire_order(&mut self, id: u32) {
+        self.done_orders.push_back(id);
+        while self.done_orders.len() > DONE_ORDERS_KEPT {
+            let id = match self.done_orders.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            if self.orders.contains_key(&id) {
+                continue;
+            }
+            self.filled_orders.remove(&id);
+            if let Some(client_order) = self.client_ids.remove(&id) {
+                if self.client_orders.get(&client_order) == Some(&id) {
+                    self.client_orders.remove(&client_order);
+                }
+            }
+        }
+    }
+
+    // puts an order into the bids/asks and reports the new top of book if it changed
+    fn rest_order(&mut self, new_order: Order) {
+        let p = new_order.price;
+        let side = new_order.side;
+        let hidden = new_order.hidden;
+        let order_side = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let best_price = order_side.displayed_price_queue().map(|queue| queue.price);
+        let value = order_side.add_order(new_order);
+        self.index_order(value);
+        if hidden {
+            return;
+        }
+        let at_top = match (side, best_price) {
+            (_, None) => true,
+            (Side::Buy, Some(best_price)) => p >= best_price,
+            (Side::Sell, Some(best_price)) => p <= best_price,
+        };
+        if at_top {
+            self.log_top_of_book(side);
+        }
+    }
+
+    // price a pegged order should currently rest at. References only come from
+    // orders that are not pegged themselves, and the result is kept one tick
+    // behind the opposite touch so a peg never takes liquidity
+    fn peg_price(&self, side: Side, peg: Peg) -> Option<usize> {
+        let best_bid = self.bids.reference_price();
+        let best_ask = self.asks.reference_price();
+        let reference = match (peg.peg_type, side) {
+            (PegType::Primary, Side::Buy) | (PegType::Market, Side::Sell) => best_bid?,
+            (PegType::Primary, Side::Sell) | (PegType::Market, Side::Buy) => best_ask?,
+            (PegType::Midpoint, Side::Buy) => (best_bid? + best_ask?) / 2,
+            (PegType::Midpoint, Side::Sell) => (best_bid? + best_ask?).div_ceil(2),
+        };
+        let price = reference as isize + peg.offset;
+        if price <= 0 {
+            return None;
+        }
+        let price = match side {
+            Side::Buy => (price as usize).min(self.asks.min_price().saturating_sub(1)),
+            Side::Sell => (price as usize).max(self.bids.max_price() + 1),
+        };
+        Some(price).filter(|price| *price > 0)
+    }
+
+    // moves pegged orders after the book changed. Pegs are repriced one by one in
+    // the order they were submitted, and again while any of them moved since one
+    // peg can bound another. A peg whose price did not change keeps its queue
+    // position, a repriced one joins the back of the queue at its new price and
+    // one that lost its reference price is pulled with an `X` message
+    fn reprice_pegs(&mut self) {
+        // bounded so pegs pushing each other around can't keep going forever
+        for _ in 0..=self.pegged.len() {
+            let orders = &self.orders;
+            self.pegged.retain(|id| orders.contains_key(id));
+            let mut moved = false;
+            for id in self.pegged.clone() {
+                let order = match self.orders.get(&id) {
+                    Some(order) => order.clone(),
+                    None => continue,
+                };
+                let new_price = match order.peg.and_then(|peg| self.peg_price(order.side, peg)) {
+                    Some(new_price) if new_price == order.price => continue,
+                    Some(new_price) => new_price,
+                    None => {
+                        self.log(format!(
+                            "X, {:?}, {:?}, {:?}, PEG{}",
+                            order.user_id,
+                            order.id,
+                            order.quantity,
+                            self.client_order_ids(&[order.id])
+                        ));
+                        self.withdraw_order(id);
+                        moved = true;
+                        continue;
+                    }
+                };
+                self.log(format!(
+                    "P, {:?}, {:?}, {:?}, {:?}{}",
+                    order.user_id,
+                    order.id,
+                    order.price,
+                    new_price,
+                    self.client_order_ids(&[order.id])
+                ));
+                let removed_order = match order.side {
+                    Side::Buy => self.bids.remove_order_internal(&order, &self.tx),
+                    Side::Sell => self.asks.remove_order_internal(&order, &self.tx),
+                };
+                if let Some(mut repriced_order) = removed_order {
+                    repriced_order.price = new_price;
+                    repriced_order.updated_at = self.clock.now();
+                    self.rest_order(repriced_order);
+                }
+                moved = true;
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    // bookkeeping for orders that just traded `quantity`, wherever the fill came from
+    fn on_fill(&mut self, id: u32, linked_id: Option<u32>, quantity: usize) {
+        if let Some(linked_id) = linked_id {
+            let filled_leg_done = !self.orders.contains_key(&id);
+            self.reduce_linked_order(linked_id, quantity, filled_leg_done);
+        }
+        if self.brackets.contains_key(&id) {
+            self.pending_brackets.push((id, quantity));
+        }
+    }
+
+    // puts the take profit and stop loss of bracket entries that traded into
+    // the book, sized by how much of the entry filled. They are only submitted
+    // once the matching that filled the entry is over
+    fn activate_brackets(&mut self) {
+        while !self.pending_brackets.is_empty() {
+            let (id, quantity) = self.pending_brackets.remove(0);
+            let (take_profit, stop_loss) = match self.brackets.get(&id) {
+                Some(bracket) => bracket.clone(),
+                None => continue,
+            };
+            let live_children: Vec<Order> = [take_profit.id, stop_loss.id]
+                .iter()
+                .filter_map(|id| self.orders.get(id).cloned())
+                .collect();
+            if live_children.is_empty() {
+                let mut take_profit = take_profit;
+                let mut stop_loss = stop_loss;
+                take_profit.quantity = quantity;
+                stop_loss.quantity = quantity;
+                self.add_oco_order(take_profit, stop_loss);
+                continue;
+            }
+            let mut surviving_leg = None;
+            for child in &live_children {
+                let increased_order = if child.is_stop() {
+                    self.stops.increase_order(child, quantity)
+                } else {
+                    match child.side {
+                        Side::Buy => self.bids.increase_order(child, quantity),
+                        Side::Sell => self.asks.increase_order(child, quantity),
+                    }
+                };
+                if let Some(increased_order) = increased_order {
+                    self.log(format!(
+                        "O, {:?}, {:?}, {:?}{}",
+                        child.user_id,
+                        child.id,
+                        increased_order.quantity,
+                        self.client_order_ids(&[child.id])
+                    ));
+                    if !child.is_stop() && !child.hidden {
+                        self.log_top_of_book(child.side);
+                    }
+                    surviving_leg = Some((increased_order.id, increased_order.quantity));
+                    self.index_order(increased_order);
+                }
+            }
+            // a leg that went away without closing the position, e.g. a stop
+            // that found nothing to trade, is put back next to the other one
+            if let (1, Some((surviving_id, quantity))) = (live_children.len(), surviving_leg) {
+                let mut missing_leg = if surviving_id == take_profit.id {
+                    stop_loss
+                } else {
+                    take_profit
+                };
+                missing_leg.quantity = quantity;
+                missing_leg.linked_id = Some(surviving_id);
+                self.add_order(missing_leg);
+            }
+        }
+        // entries that are done have no children left to activate
+        let orders = &self.orders;
+        self.brackets.retain(|id, _| orders.contains_key(id));
+    }
+
+    // takes what traded on one OCO leg off the other leg, or cancels the other
+    // leg outright once the traded leg is done
+    fn reduce_linked_order(&mut self, id: u32, quantity: usize, cancel: bool) {
+        let order = match self.orders.get(&id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        let remaining = order.quantity + order.reserve_quantity;
+        if cancel || quantity >= remaining {
+            self.log(format!(
+                "O, {:?}, {:?}, 0{}",
+                order.user_id,
+                order.id,
+                self.client_order_ids(&[order.id])
+            ));
+            self.cancel_order(id);
+            self.retire_order(id);
+            return;
+        }
+        let reduced_order = if order.is_stop() {
+            self.stops.reduce_order(&order, quantity)
+        } else {
+            match order.side {
+                Side::Buy => self.bids.reduce_order(&order, quantity),
+                Side::Sell => self.asks.reduce_order(&order, quantity),
+            }
+        };
+        if let Some(reduced_order) = reduced_order {
+            self.log(format!(
+                "O, {:?}, {:?}, {:?}{}",
+                order.user_id,
+                order.id,
+                remaining - quantity,
+                self.client_order_ids(&[order.id])
+            ));
+            if !order.is_stop() && !order.hidden {
+                self.log_top_of_book(order.side);
+            }
+            self.index_order(reduced_order);
+        }
+    }
+
+    // a resting all or none order is passed over by orders too small to fill
+    // it, and what is left of those may rest across it. Once the liquidity
+    // across it adds up to its whole size it trades as the taker
+    fn match_all_or_none(&mut self) {
+        while let Some(order) = self.fillable_all_or_none() {
+            let side = order.side;
+            let comparator = match side {
+                Side::Buy => Self::greater_than_or_equal,
+                Side::Sell => Self::lower_than_or_equal,
+            };
+            let top_of_book = |book: &Self| {
+                match side {
+                    Side::Buy => book.bids.displayed_price_queue(),
+                    Side::Sell => book.asks.displayed_price_queue(),
+                }
+                .map(|queue| (queue.price, queue.volume))
+            };
+            let old_top_of_book = top_of_book(self);
+            match side {
+                Side::Buy => self.bids.take_order(&order),
+                Side::Sell => self.asks.take_order(&order),
+            };
+            self.unindex_order(order.id);
+
+            let mut quantity_left = order.quantity;
+            let mut level_price = None;
+            while quantity_left > 0 {
+                level_price = match side {
+                    Side::Buy => self.asks.next_price_queue(level_price),
+                    Side::Sell => self.bids.next_price_queue(level_price),
+                }
+                .map(|queue| queue.price)
+                .filter(|price| comparator(&order.price, price));
+                let price = match level_price {
+                    Some(price) => price,
+                    None => break,
+                };
+                quantity_left = self
+                    .process_queue(side, price, quantity_left, order.user_id, order.id)
+                    .quantity_left;
+            }
+            if top_of_book(self) != old_top_of_book {
+                self.log_top_of_book(side);
+            }
+            self.filled_orders.insert(order.id);
+            self.retire_order(order.id);
+            self.on_fill(order.id, order.linked_id, order.quantity - quantity_left);
+        }
+    }
+
+    // best priced resting all or none order the other side can fill in full
+    fn fillable_all_or_none(&self) -> Option<Order> {
+        for (orders, other) in [(&self.bids, &self.asks), (&self.asks, &self.bids)] {
+            let comparator = match orders.side {
+                Side::Buy => Self::greater_than_or_equal,
+                Side::Sell => Self::lower_than_or_equal,
+            };
+            let best_price = match other.next_price_queue(None) {
+                Some(order_queue) => order_queue.price,
+                None => continue,
+            };
+            let mut level = orders.next_price_queue(None);
+            while let Some(order_queue) = level {
+                if !comparator(&order_queue.price, &best_price) {
+                    break;
+                }
+                let fillable = order_queue.iter().find(|order| {
+                    order.all_or_none
+                        && other.available_volume(order.price, order.quantity) >= order.quantity
+                });
+                if let Some(order) = fillable {
+                    return Some(order.clone());
+                }
+                level = orders.next_price_queue(Some(order_queue.price));
+            }
+        }
+        None
+    }
+
+    fn log_top_of_book(&self, side: Side) {
+        let (code, queue) = match side {
+            Side::Buy => ("B", self.bids.displayed_price_queue()),
+            Side::Sell => ("S", self.asks.displayed_price_queue()),
+        };
+        match queue {
+            Some(queue) => self.log(format!(
+                "B, {}, {:?}, {:?}",
+                code, queue.price, queue.volume
+            )),
+            None => self.log(format!("B, {}, -, -", code)),
+        }
+    }
+
+    fn update_trailing_stops(&mut self, trade_price: usize) {
+        for order in self.stops.update_trailing(trade_price) {
+            self.index_order(order);
+        }
+    }
+
+    fn greater_than_or_equal(a: &usize, b: &usize) -> bool {
+        a >= b
+    }
+    fn lower_than_or_equal(a: &usize, b: &usize) -> bool {
+        a <= b
+    }
+
+    // matches against the level at `level_price` until the order is filled or
+    // the level has nothing left it can fill
+    fn process_queue(
+        &mut self,
+        side: Side,
+        level_price: usize,
+        quantity_to_trade: usize,
+        user_id: u32,
+        id: u32,
+    ) -> InProcessOrder {
+        let mut in_process_order = InProcessOrder::new(quantity_to_trade);
+        let taker_client_id = self
+            .client_ids
+            .get(&id)
+            .map(|(_, client_id)| client_id.clone());
+
+        while in_process_order.quantity_left > 0 {
+            let order_queue = match side {
+                Side::Buy => self.asks.price_queue_mut(level_price),
+                Side::Sell => self.bids.price_queue_mut(level_price),
+            };
+            let order_queue = match order_queue {
+                Some(order_queue) => order_queue,
+                None => break,
+            };
+            // all or none orders that are too big are skipped, they keep their place
+            let head_order = match order_queue.first_fillable(in_process_order.quantity_left) {
+                Some(head_order) => head_order.clone(),
+                None => break,
+            };
+            let head_size = head_order.quantity;
+            let filled_quantity = in_process_order.quantity_left.min(head_size);
+            let (filled_id, filled_link) = (head_order.id, head_order.linked_id);
+            let head_hidden = head_order.hidden;
+            self.last_trade_price = Some(head_order.price);
+            if in_process_order.quantity_left < head_size {
+                let mut new_order = head_order.clone();
+                new_order.quantity = head_size - in_process_order.quantity_left;
+
+                let msg = format!(
+                    "T, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}{}",
+                    user_id,
+                    id,
+                    head_order.user_id,
+                    head_order.id,
+                    head_order.price,
+                    in_process_order.quantity_left,
+                    Self::format_client_ids(&[
+                        taker_client_id.as_ref(),
+                        head_order.client_order_id.as_ref()
+                    ])
+                );
+
+                in_process_order.partial = Some(new_order.clone());
+                self.orders.insert(new_order.id, new_order.clone());
+                in_process_order.partial_quantity_processed = in_process_order.quantity_left;
+                order_queue.update(new_order.clone());
+                new_order.quantity = in_process_order.quantity_left;
+                self.log(msg);
+                let top_price = match side {
+                    Side::Buy => self.asks.displayed_price_queue(),
+                    Side::Sell => self.bids.displayed_price_queue(),
+                }
+                .map(|queue| queue.price);
+                // nothing displayed changed for hidden orders or when all or none
+                // orders above the traded level are still there
+                if !head_hidden && top_price == Some(level_price) {
+                    self.log(format!(
+                        "B, {}, {:?}, {:?}",
+                        if side == Side::Buy { "S" } else { "B" },
+                        level_price,
+                        head_size - in_process_order.quantity_left
+                    ));
+                }
+
+                in_process_order.quantity_left = 0;
+
+                match new_order.side {
+                    Side::Buy => self.bids.decrease_volume_and_total(&new_order),
+                    Side::Sell => self.asks.decrease_volume_and_total(&new_order),
+                };
+            } else {
+                in_process_order.quantity_left -= head_size;
+                let msg = format!(
+                    "T, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}{}",
+                    user_id,
+                    id,
+                    head_order.user_id,
+                    head_order.id,
+                    head_order.price,
+                    head_order.quantity,
+                    Self::format_client_ids(&[
+                        taker_client_id.as_ref(),
+                        head_order.client_order_id.as_ref()
+                    ])
+                );
+                self.log(msg);
+                if head_order.reserve_quantity > 0 {
+                    let refilled_order = match head_order.side {
+                        Side::Buy => self.bids.refill_order(&head_order),
+                        Side::Sell => self.asks.refill_order(&head_order),
+                    };
+                    if let Some(refilled_order) = refilled_order {
+                        let order_side = match head_order.side {
+                            Side::Buy => &self.bids,
+                            Side::Sell => &self.asks,
+                        };
+                        // all or none orders skipped above may still hold the top
+                        let at_top = order_side
+                            .displayed_price_queue()
+                            .is_some_and(|queue| queue.price == level_price);
+                        if at_top {
+                            let volume = order_side.price_queue(level_price).unwrap().volume;
+                            self.log(format!(
+                                "B, {}, {:?}, {:?}",
+                                if head_order.side == Side::Buy {
+                                    "B"
+                                } else {
+                                    "S"
+                                },
+                                refilled_order.price,
+                                volume
+                            ));
+                        }
+                        // the slice traded in full, the refill is a new slice
+                        in_process_order.done.push(head_order.clone());
+                        self.index_order(refilled_order);
+                    }
+                } else if let Some(canceled_order) = self.cancel_order(head_order.id) {
+                    self.filled_orders.insert(head_order.id);
+                    self.retire_order(head_order.id);
+                    in_process_order.done.push(canceled_order);
+                };
+            }
+            self.update_trailing_stops(level_price);
+            self.on_fill(filled_id, filled_link, filled_quantity);
+        }
+        in_process_order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::scale_order::Distribution;
+    use chrono::{Duration, TimeZone, Utc};
+    use std::sync::mpsc::{self, Receiver};
+
+    fn order_book() -> (OrderBook, Receiver<String>) {
+        let (tx, rx) = mpsc::channel();
+        (OrderBook::new("TEST".to_string(), true, Some(tx)), rx)
+    }
+
+    fn messages(rx: &Receiver<String>) -> Vec<String> {
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn test_add_limit_order_rests() {
+        let (mut book, rx) = order_book();
+        let order = book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        assert!(order.err.is_none());
+        assert_eq!(order.quantity_left, 10);
+        assert_eq!(book.bids.volume, 10);
+        assert!(book.orders.contains_key(&1));
+        assert_eq!(messages(&rx), vec!["A, 1, 1", "B, B, 100, 10"]);
+    }
+
+    #[test]
+    fn test_add_limit_order_does_not_trade_through_limit() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 105, 1, 2, TimeInForce::GTC);
+        let order = book.add_limit_order(Side::Buy, 15, 100, 2, 3, TimeInForce::GTC);
+        assert_eq!(order.done.len(), 1);
+        assert_eq!(order.quantity_left, 5);
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(book.asks.min_price(), 105);
+        assert_eq!(book.orders.get(&3).unwrap().quantity, 5);
+    }
+
+    #[test]
+    fn test_add_limit_order_sweeps_whole_side() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 2, TimeInForce::GTC);
+        let order = book.add_limit_order(Side::Buy, 30, 101, 2, 3, TimeInForce::GTC);
+        assert_eq!(order.quantity_left, 10);
+        assert_eq!(book.asks.num_orders, 0);
+        assert_eq!(book.bids.volume, 10);
+    }
+
+    #[test]
+    fn test_ioc_cancels_remainder() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_limit_order(Side::Buy, 15, 100, 2, 2, TimeInForce::IOC);
+        assert!(order.err.is_none());
+        assert_eq!(order.quantity_left, 5);
+        assert_eq!(order.cancelled_quantity, 5);
+        assert_eq!(order.done.len(), 1);
+        assert_eq!(book.bids.num_orders, 0);
+        assert!(!book.orders.contains_key(&2));
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 2, 2",
+                "T, 2, 2, 1, 1, 100, 10",
+                "B, S, -, -",
+                "X, 2, 2, 5, IOC"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ioc_without_match_never_rests() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 105, 1, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_limit_order(Side::Buy, 10, 100, 2, 2, TimeInForce::IOC);
+        assert_eq!(order.cancelled_quantity, 10);
+        assert!(order.done.is_empty());
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(messages(&rx), vec!["A, 2, 2", "X, 2, 2, 10, IOC"]);
+    }
+
+    #[test]
+    fn test_ioc_fully_filled() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        let order = book.add_limit_order(Side::Sell, 10, 100, 2, 2, TimeInForce::IOC);
+        assert_eq!(order.quantity_left, 0);
+        assert_eq!(order.cancelled_quantity, 0);
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(book.asks.num_orders, 0);
+    }
+
+    #[test]
+    fn test_fok_fills_across_levels() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 102, 1, 3, TimeInForce::GTC);
+        let order = book.add_limit_order(Side::Buy, 25, 102, 2, 4, TimeInForce::FOK);
+        assert!(order.err.is_none());
+        assert_eq!(order.quantity_left, 0);
+        assert_eq!(book.asks.volume, 5);
+        assert_eq!(book.bids.num_orders, 0);
+    }
+
+    #[test]
+    fn test_fok_rejected_without_side_effects() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 102, 1, 3, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_limit_order(Side::Buy, 25, 101, 2, 4, TimeInForce::FOK);
+        assert_eq!(
+            order.err,
+            Some("Fill or kill order cannot be filled".to_string())
+        );
+        assert!(order.done.is_empty());
+        assert_eq!(book.asks.volume, 30);
+        assert_eq!(book.asks.num_orders, 3);
+        assert!(!book.orders.contains_key(&4));
+        assert_eq!(messages(&rx), vec!["R, 2, 4"]);
+    }
+
+    fn gtd_order(id: u32, price: usize, expires_at: DateTime<Utc>) -> Order {
+        let mut order = Order::new(
+            id,
+            1,
+            Side::Buy,
+            OrderType::Limit,
+            TimeInForce::GTD,
+            price,
+            10,
+        );
+        order.expires_at = Some(expires_at);
+        order
+    }
+
+    #[test]
+    fn test_expire_orders() {
+        let (mut book, rx) = order_book();
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let clock = ManualClock::new(start);
+        book.set_clock(Box::new(clock.clone()));
+
+        book.add_order(gtd_order(1, 100, start + Duration::minutes(5)));
+        book.add_order(gtd_order(2, 99, start + Duration::minutes(10)));
+        book.add_limit_order(Side::Buy, 10, 98, 1, 3, TimeInForce::GTC);
+        messages(&rx);
+
+        assert!(book.expire_orders().is_empty());
+        clock.advance(Duration::minutes(5));
+        let expired = book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, 1);
+        assert_eq!(messages(&rx), vec!["E, 1, 1", "B, B, 99, 10"]);
+
+        clock.advance(Duration::hours(1));
+        assert_eq!(book.expire_orders().len(), 1);
+        assert_eq!(book.bids.num_orders, 1);
+        assert!(book.orders.contains_key(&3));
+    }
+
+    #[test]
+    fn test_expired_oco_leg_cancels_the_other() {
+        let (mut book, rx) = order_book();
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let clock = ManualClock::new(start);
+        book.set_clock(Box::new(clock.clone()));
+
+        let mut take_profit = gtd_order(1, 100, start + Duration::minutes(5));
+        take_profit.side = Side::Sell;
+        let mut stop_loss = Order::new(
+            2,
+            1,
+            Side::Sell,
+            OrderType::StopMarket,
+            TimeInForce::GTC,
+            0,
+            10,
+        );
+        stop_loss.stop_price = 90;
+        book.add_oco_order(take_profit, stop_loss);
+        messages(&rx);
+
+        clock.advance(Duration::minutes(5));
+        assert_eq!(book.expire_orders().len(), 1);
+        assert!(book.orders.is_empty());
+        assert_eq!(book.stops.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["E, 1, 1", "B, S, -, -", "A, 1, 2"]);
+    }
+
+    #[test]
+    fn test_gtd_order_rejected_when_already_expired() {
+        let (mut book, rx) = order_book();
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        book.set_clock(Box::new(ManualClock::new(start)));
+
+        let order = book.add_order(gtd_order(1, 100, start));
+        assert_eq!(order.err, Some("Order already expired".to_string()));
+        let order = book.add_order(gtd_order(2, 100, start - Duration::seconds(1)));
+        assert_eq!(order.err, Some("Order already expired".to_string()));
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["R, 1, 1", "R, 1, 2"]);
+    }
+
+    #[test]
+    fn test_gtd_order_requires_expiry() {
+        let (mut book, rx) = order_book();
+        let order = book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTD);
+        assert_eq!(
+            order.err,
+            Some("Use add_gtd_order for GTD orders".to_string())
+        );
+        let mut order = gtd_order(2, 100, Utc::now());
+        order.expires_at = None;
+        let order = book.add_order(order);
+        assert_eq!(order.err, Some("Missing expiry for GTD order".to_string()));
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["R, 1, 1", "R, 1, 2"]);
+    }
+
+    #[test]
+    fn test_add_gtd_order() {
+        let (mut book, _rx) = order_book();
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let clock = ManualClock::new(start);
+        book.set_clock(Box::new(clock.clone()));
+        let expires_at = start + Duration::minutes(5);
+        let order = book.add_gtd_order(Side::Buy, 10, 100, 1, 1, expires_at);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&1).unwrap().expires_at, Some(expires_at));
+        clock.advance(Duration::minutes(5));
+        assert_eq!(book.expire_orders().len(), 1);
+    }
+
+    #[test]
+    fn test_iceberg_order_displays_peak_only() {
+        let (mut book, rx) = order_book();
+        let order = book.add_iceberg_order(Side::Sell, 100, 100, 10, 1, 1);
+        assert!(order.err.is_none());
+        assert_eq!(book.asks.volume, 10);
+        let order_queue = book.asks.min_price_queue().unwrap();
+        assert_eq!(order_queue.volume, 10);
+        assert_eq!(order_queue.hidden_volume, 90);
+        assert_eq!(messages(&rx), vec!["A, 1, 1", "B, S, 100, 10"]);
+    }
+
+    #[test]
+    fn test_iceberg_order_refills_and_loses_priority() {
+        let (mut book, rx) = order_book();
+        book.add_iceberg_order(Side::Sell, 25, 100, 10, 1, 1);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 10, 100, 3, 3, TimeInForce::GTC);
+        assert_eq!(
+            messages(&rx),
+            vec!["A, 3, 3", "T, 3, 3, 1, 1, 100, 10", "B, S, 100, 15"]
+        );
+        let order_queue = book.asks.min_price_queue().unwrap();
+        assert_eq!(order_queue.iter().next().unwrap().id, 2);
+        assert_eq!(order_queue.hidden_volume, 5);
+        let iceberg = book.orders.get(&1).unwrap();
+        assert_eq!(iceberg.quantity, 10);
+        assert_eq!(iceberg.reserve_quantity, 5);
+
+        // the plain order now trades first, then the iceberg refills again
+        let order = book.add_limit_order(Side::Buy, 15, 100, 3, 4, TimeInForce::GTC);
+        assert_eq!(order.quantity_left, 0);
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 5);
+        assert_eq!(book.orders.get(&1).unwrap().reserve_quantity, 0);
+        assert!(!book.orders.contains_key(&2));
+        assert_eq!(book.asks.volume, 5);
+    }
+
+    #[test]
+    fn test_order_crossing_several_iceberg_refills() {
+        let (mut book, _rx) = order_book();
+        book.add_iceberg_order(Side::Sell, 30, 100, 10, 1, 1);
+        let order = book.add_limit_order(Side::Buy, 25, 100, 2, 2, TimeInForce::GTC);
+        assert_eq!(order.quantity_left, 0);
+        let fills: Vec<(u32, usize)> = order.done.iter().map(|o| (o.id, o.quantity)).collect();
+        assert_eq!(fills, vec![(1, 10), (1, 10), (2, 25)]);
+        assert_eq!(order.partial.unwrap().quantity, 5);
+        assert_eq!(order.partial_quantity_processed, 5);
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 5);
+    }
+
+    #[test]
+    fn test_fok_counts_iceberg_reserve() {
+        let (mut book, _rx) = order_book();
+        book.add_iceberg_order(Side::Sell, 50, 100, 10, 1, 1);
+        let order = book.add_limit_order(Side::Buy, 40, 100, 2, 2, TimeInForce::FOK);
+        assert!(order.err.is_none());
+        assert_eq!(order.quantity_left, 0);
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(book.orders.get(&1).unwrap().reserve_quantity, 0);
+    }
+
+    #[test]
+    fn test_iceberg_order_requires_peak() {
+        let (mut book, _rx) = order_book();
+        let order = book.add_iceberg_order(Side::Sell, 50, 100, 0, 1, 1);
+        assert_eq!(order.err, Some("Invalid peak quantity".to_string()));
+    }
+
+    #[test]
+    fn test_stop_market_order_triggers_on_trade() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 2, TimeInForce::GTC);
+        let order = book.add_stop_order(Side::Buy, 5, 100, None, 2, 3);
+        assert!(order.err.is_none());
+        assert_eq!(book.stops.num_orders, 1);
+        assert_eq!(book.asks.volume, 20);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 5, 100, 3, 4, TimeInForce::GTC);
+        assert_eq!(book.last_trade_price(), Some(100));
+        assert_eq!(book.stops.num_orders, 0);
+        assert!(!book.orders.contains_key(&3));
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 3, 4",
+                "T, 3, 4, 1, 1, 100, 5",
+                "B, S, 100, 5",
+                "S, 2, 3, 100",
+                "A, 2, 3",
+                "T, 2, 3, 1, 1, 100, 5",
+                "B, S, 101, 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stop_limit_order_rests_when_triggered() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_stop_order(Side::Sell, 5, 100, Some(102), 2, 2);
+        assert_eq!(book.asks.num_orders, 0);
+
+        book.add_limit_order(Side::Sell, 1, 100, 3, 3, TimeInForce::GTC);
+        let stop_limit = book.orders.get(&2).unwrap();
+        assert_eq!(stop_limit.order_type, OrderType::Limit);
+        assert_eq!(book.asks.min_price(), 102);
+        assert_eq!(book.asks.volume, 5);
+    }
+
+    #[test]
+    fn test_stop_orders_cascade_in_order() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 102, 1, 3, TimeInForce::GTC);
+        book.add_stop_order(Side::Buy, 10, 102, None, 2, 5);
+        book.add_stop_order(Side::Buy, 10, 101, None, 2, 4);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 10, 100, 3, 6, TimeInForce::GTC);
+        assert_eq!(book.stops.num_orders, 2);
+        messages(&rx);
+        book.add_market_order(Side::Buy, 5, 3, 7);
+        assert_eq!(book.stops.num_orders, 0);
+        assert_eq!(book.asks.num_orders, 0);
+        let stops: Vec<String> = messages(&rx)
+            .into_iter()
+            .filter(|msg| msg.starts_with("S,") || msg.starts_with("T,"))
+            .collect();
+        assert_eq!(
+            stops,
+            vec![
+                "T, 3, 7, 1, 2, 101, 5",
+                "S, 2, 4, 101",
+                "T, 2, 4, 1, 2, 101, 5",
+                "T, 2, 4, 1, 3, 102, 5",
+                "S, 2, 5, 102",
+                "T, 2, 5, 1, 3, 102, 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancel_stop_order() {
+        let (mut book, rx) = order_book();
+        book.add_stop_order(Side::Buy, 10, 105, None, 1, 1);
+        messages(&rx);
+        assert!(matches!(
+            book.cancel_order_user(1, 1),
+            CancelResult::Cancelled(_)
+        ));
+        assert_eq!(book.stops.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["A, 1, 1"]);
+    }
+
+    #[test]
+    fn test_post_only_order_rejected_when_crossing() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_post_only_order(Side::Buy, 10, 100, 2, 2, PostOnly::Reject);
+        assert_eq!(
+            order.err,
+            Some("Post only order would take liquidity".to_string())
+        );
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["R, 2, 2"]);
+    }
+
+    #[test]
+    fn test_post_only_order_slides_behind_touch() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 90, 1, 2, TimeInForce::GTC);
+        messages(&rx);
+
+        let order = book.add_post_only_order(Side::Buy, 10, 105, 2, 3, PostOnly::Slide);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&3).unwrap().price, 99);
+        assert_eq!(book.asks.volume, 10);
+        assert_eq!(messages(&rx), vec!["A, 2, 3", "B, B, 99, 10"]);
+
+        book.add_post_only_order(Side::Sell, 10, 80, 2, 4, PostOnly::Slide);
+        assert_eq!(book.orders.get(&4).unwrap().price, 100);
+        assert_eq!(book.asks.volume, 20);
+    }
+
+    #[test]
+    fn test_post_only_order_rests_when_passive() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        let order = book.add_post_only_order(Side::Buy, 10, 95, 2, 2, PostOnly::Reject);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&2).unwrap().price, 95);
+    }
+
+    #[test]
+    fn test_trailing_stop_follows_market() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 1, 100, 2, 2, TimeInForce::GTC);
+        let order =
+            book.add_trailing_stop_order(Side::Sell, 5, TrailingOffset::Fixed(5), None, 3, 3);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&3).unwrap().stop_price, 95);
+
+        // the market trades up, the stop follows
+        book.add_limit_order(Side::Sell, 1, 110, 4, 4, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 1, 110, 5, 5, TimeInForce::GTC);
+        let trailing_stop = book.orders.get(&3).unwrap();
+        assert_eq!(trailing_stop.stop_price, 105);
+        assert_eq!(trailing_stop.trailing_stop.unwrap().watermark, 110);
+        messages(&rx);
+
+        // and triggers once it turns back down through the offset
+        book.add_limit_order(Side::Buy, 10, 105, 6, 6, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 1, 105, 7, 7, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&3));
+        assert_eq!(book.stops.num_orders, 0);
+        assert_eq!(book.bids.volume, 13);
+        let msgs = messages(&rx);
+        assert!(msgs.contains(&"S, 3, 3, 105".to_string()));
+        assert!(msgs.contains(&"T, 3, 3, 6, 6, 105, 5".to_string()));
+    }
+
+    #[test]
+    fn test_trailing_stop_releases_limit_order() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 1, 100, 2, 2, TimeInForce::GTC);
+        book.add_trailing_stop_order(Side::Buy, 5, TrailingOffset::Percent(10.0), Some(2), 3, 3);
+        assert_eq!(book.orders.get(&3).unwrap().stop_price, 110);
+
+        book.add_limit_order(Side::Buy, 1, 90, 4, 4, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 1, 90, 5, 5, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&3).unwrap().stop_price, 99);
+
+        messages(&rx);
+        book.add_limit_order(Side::Buy, 1, 100, 6, 6, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&3));
+        assert_eq!(book.asks.volume, 3);
+        let msgs = messages(&rx);
+        assert!(msgs.contains(&"S, 3, 3, 99".to_string()));
+        assert!(msgs.contains(&"T, 3, 3, 1, 1, 100, 5".to_string()));
+    }
+
+    #[test]
+    fn test_trailing_stop_requires_last_trade() {
+        let (mut book, rx) = order_book();
+        let order =
+            book.add_trailing_stop_order(Side::Sell, 5, TrailingOffset::Fixed(5), None, 1, 1);
+        assert_eq!(
+            order.err,
+            Some("No last trade for trailing stop".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 1"]);
+    }
+
+    #[test]
+    fn test_trailing_stop_percent_must_be_within_range() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 1, 100, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        for (id, percent) in [(3, 100.0), (4, 150.0), (5, 0.0), (6, -5.0), (7, f64::NAN)] {
+            let order = book.add_trailing_stop_order(
+                Side::Sell,
+                5,
+                TrailingOffset::Percent(percent),
+                None,
+                3,
+                id,
+            );
+            assert_eq!(order.err, Some("Invalid trailing percent".to_string()));
+        }
+        let order = book.add_trailing_stop_order(
+            Side::Sell,
+            5,
+            TrailingOffset::Percent(f64::INFINITY),
+            None,
+            3,
+            8,
+        );
+        assert_eq!(order.err, Some("Invalid trailing percent".to_string()));
+        assert_eq!(book.stops.num_orders, 0);
+
+        let order =
+            book.add_trailing_stop_order(Side::Sell, 5, TrailingOffset::Percent(99.0), None, 3, 9);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&9).unwrap().stop_price, 1);
+    }
+
+    #[test]
+    fn test_primary_peg_follows_best_bid() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 110, 1, 2, TimeInForce::GTC);
+        let order = book.add_pegged_order(Side::Buy, 5, PegType::Primary, 0, 2, 3);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.get(&3).unwrap().price, 100);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 10, 102, 1, 4, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&3).unwrap().price, 102);
+        let order_queue = book.bids.max_price_queue().unwrap();
+        assert_eq!(order_queue.iter().next().unwrap().id, 4);
+        assert_eq!(order_queue.volume, 15);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 1, 4",
+                "B, B, 102, 10",
+                "P, 2, 3, 100, 102",
+                "B, B, 102, 15"
+            ]
+        );
+
+        book.cancel_order_user(1, 4);
+        assert_eq!(book.orders.get(&3).unwrap().price, 100);
+        assert_eq!(book.bids.volume, 15);
+    }
+
+    #[test]
+    fn test_hidden_order_never_moves_peg() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 99, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 105, 2, 2, TimeInForce::GTC);
+        book.add_pegged_order(Side::Buy, 5, PegType::Primary, 0, 4, 4);
+        messages(&rx);
+
+        book.add_hidden_order(Side::Buy, 10, 101, 3, 3, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&4).unwrap().price, 99);
+        assert_eq!(messages(&rx), vec!["A, 3, 3"]);
+    }
+
+    #[test]
+    fn test_market_peg_stays_behind_touch() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 110, 1, 2, TimeInForce::GTC);
+        book.add_pegged_order(Side::Buy, 5, PegType::Market, -2, 2, 3);
+        book.add_pegged_order(Side::Buy, 5, PegType::Market, 0, 2, 4);
+        assert_eq!(book.orders.get(&3).unwrap().price, 108);
+        // without an offset it would cross, so it rests one tick behind the offer
+        assert_eq!(book.orders.get(&4).unwrap().price, 109);
+        assert_eq!(book.asks.volume, 10);
+
+        book.add_limit_order(Side::Sell, 10, 120, 1, 5, TimeInForce::GTC);
+        book.cancel_order_user(1, 2);
+        assert_eq!(book.orders.get(&3).unwrap().price, 118);
+        assert_eq!(book.orders.get(&4).unwrap().price, 119);
+    }
+
+    #[test]
+    fn test_midpoint_peg() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 111, 1, 2, TimeInForce::GTC);
+        book.add_pegged_order(Side::Buy, 5, PegType::Midpoint, 0, 2, 3);
+        assert_eq!(book.orders.get(&3).unwrap().price, 105);
+        book.cancel_order_user(2, 3);
+        book.add_pegged_order(Side::Sell, 5, PegType::Midpoint, 1, 2, 4);
+        assert_eq!(book.orders.get(&4).unwrap().price, 107);
+
+        book.add_limit_order(Side::Buy, 10, 104, 1, 5, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&4).unwrap().price, 109);
+    }
+
+    #[test]
+    fn test_peg_without_reference_is_pulled() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 110, 1, 2, TimeInForce::GTC);
+        book.add_pegged_order(Side::Buy, 5, PegType::Primary, 0, 2, 3);
+        messages(&rx);
+
+        book.cancel_order_user(1, 1);
+        assert!(!book.orders.contains_key(&3));
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(
+            messages(&rx),
+            vec!["A, 1, 1", "B, B, 100, 10", "X, 2, 3, 5, PEG", "B, B, -, -"]
+        );
+    }
+
+    #[test]
+    fn test_pegs_reprice_until_settled() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 110, 1, 2, TimeInForce::GTC);
+        book.add_pegged_order(Side::Buy, 5, PegType::Market, -2, 3, 3);
+        book.add_pegged_order(Side::Sell, 5, PegType::Primary, -5, 4, 4);
+        assert_eq!(book.orders.get(&3).unwrap().price, 108);
+        assert_eq!(book.orders.get(&4).unwrap().price, 109);
+
+        // the sell peg moves up first, which frees the buy peg to follow
+        book.add_limit_order(Side::Sell, 10, 120, 1, 5, TimeInForce::GTC);
+        book.cancel_order_user(1, 2);
+        assert_eq!(book.orders.get(&4).unwrap().price, 115);
+        assert_eq!(book.orders.get(&3).unwrap().price, 114);
+    }
+
+    #[test]
+    fn test_pegged_order_requires_reference() {
+        let (mut book, rx) = order_book();
+        let order = book.add_pegged_order(Side::Buy, 5, PegType::Midpoint, 0, 1, 1);
+        assert_eq!(
+            order.err,
+            Some("No reference price for pegged order".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 1"]);
+    }
+
+    fn oco_legs(user_id: u32) -> (Order, Order) {
+        let take_profit = Order::new(
+            1,
+            user_id,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            110,
+            10,
+        );
+        let mut stop_loss = Order::new(
+            2,
+            user_id,
+            Side::Sell,
+            OrderType::StopMarket,
+            TimeInForce::GTC,
+            0,
+            10,
+        );
+        stop_loss.stop_price = 90;
+        (take_profit, stop_loss)
+    }
+
+    #[test]
+    fn test_oco_fill_reduces_and_cancels_other_leg() {
+        let (mut book, rx) = order_book();
+        let (take_profit, stop_loss) = oco_legs(1);
+        let (first, second) = book.add_oco_order(take_profit, stop_loss);
+        assert!(first.err.is_none() && second.err.is_none());
+        assert_eq!(book.orders.get(&1).unwrap().linked_id, Some(2));
+        assert_eq!(book.orders.get(&2).unwrap().linked_id, Some(1));
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 4, 110, 2, 3, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 6);
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 6);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 2, 3",
+                "T, 2, 3, 1, 1, 110, 4",
+                "B, S, 110, 6",
+                "O, 1, 2, 6"
+            ]
+        );
+
+        book.add_limit_order(Side::Buy, 6, 110, 2, 4, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&1));
+        assert!(!book.orders.contains_key(&2));
+        assert_eq!(book.stops.num_orders, 0);
+        assert!(messages(&rx).contains(&"O, 1, 2, 0".to_string()));
+    }
+
+    #[test]
+    fn test_oco_cancel_one_leg_cancels_both() {
+        let (mut book, rx) = order_book();
+        let (take_profit, stop_loss) = oco_legs(1);
+        book.add_oco_order(take_profit, stop_loss);
+        messages(&rx);
+        assert!(matches!(
+            book.cancel_order_user(1, 2),
+            CancelResult::Cancelled(_)
+        ));
+        assert!(book.orders.is_empty());
+        assert_eq!(book.asks.num_orders, 0);
+        assert_eq!(book.stops.num_orders, 0);
+        assert_eq!(messages(&rx), vec!["A, 1, 2", "A, 1, 1", "B, S, -, -"]);
+    }
+
+    #[test]
+    fn test_oco_stop_leg_triggering_cancels_limit_leg() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 20, 90, 2, 3, TimeInForce::GTC);
+        let (take_profit, stop_loss) = oco_legs(1);
+        book.add_oco_order(take_profit, stop_loss);
+        book.add_limit_order(Side::Sell, 1, 90, 2, 4, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.asks.num_orders, 0);
+        assert_eq!(book.bids.volume, 9);
+    }
+
+    #[test]
+    fn test_oco_first_leg_trading_on_arrival() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 4, 110, 2, 3, TimeInForce::GTC);
+        let (take_profit, stop_loss) = oco_legs(1);
+        let (first, second) = book.add_oco_order(take_profit, stop_loss);
+        assert_eq!(first.quantity_left, 6);
+        assert!(second.err.is_none());
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 6);
+    }
+
+    #[test]
+    fn test_oco_rejects_duplicate_ids() {
+        let (mut book, _rx) = order_book();
+        let (take_profit, mut stop_loss) = oco_legs(1);
+        stop_loss.id = 1;
+        let (first, second) = book.add_oco_order(take_profit, stop_loss);
+        assert_eq!(first.err, Some("Order Already Exists".to_string()));
+        assert_eq!(second.err, Some("Order Already Exists".to_string()));
+        assert!(book.orders.is_empty());
+    }
+
+    fn buy_ladder(quantity: usize, start_price: usize, end_price: usize) -> ScaleOrder {
+        ScaleOrder {
+            side: Side::Buy,
+            quantity,
+            start_price,
+            end_price,
+            levels: 3,
+            distribution: Distribution::Flat,
+            user_id: 1,
+            time_in_force: TimeInForce::GTC,
+        }
+    }
+
+    #[test]
+    fn test_scale_order_places_ladder() {
+        let (mut book, rx) = order_book();
+        let parent_id = book.add_scale_order(buy_ladder(9, 100, 98)).unwrap();
+        let children = book.scale_order(parent_id).unwrap();
+        let ladder: Vec<(usize, usize)> = children.iter().map(|o| (o.price, o.quantity)).collect();
+        assert_eq!(ladder, vec![(100, 3), (99, 3), (98, 3)]);
+        assert!(children.iter().all(|o| o.user_id == 1 && o.id != parent_id));
+        assert_eq!(book.bids.volume, 9);
+        assert_eq!(messages(&rx).len(), 4);
+    }
+
+    #[test]
+    fn test_scale_order_rejected_atomically() {
+        let mut book = OrderBook::new("TEST".to_string(), false, None);
+        book.add_limit_order(Side::Sell, 5, 99, 2, 1, TimeInForce::GTC);
+        assert_eq!(
+            book.add_scale_order(buy_ladder(9, 100, 98)),
+            Err("Trading is not enabled".to_string())
+        );
+        assert_eq!(
+            book.add_scale_order(buy_ladder(2, 100, 98)),
+            Err("Quantity too small for levels".to_string())
+        );
+        assert_eq!(book.bids.num_orders, 0);
+        assert_eq!(book.orders.len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_scale_order() {
+        let (mut book, _rx) = order_book();
+        let parent_id = book.add_scale_order(buy_ladder(9, 100, 98)).unwrap();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        assert_eq!(book.scale_order(parent_id).unwrap().len(), 2);
+        assert_eq!(book.cancel_scale_order(1, parent_id).len(), 2);
+        assert!(book.scale_order(parent_id).is_none());
+        assert!(book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_amend_scale_order() {
+        let (mut book, _rx) = order_book();
+        let parent_id = book.add_scale_order(buy_ladder(9, 100, 98)).unwrap();
+        assert!(book
+            .amend_scale_order(parent_id, buy_ladder(0, 90, 88))
+            .is_err());
+        assert_eq!(book.bids.volume, 9);
+
+        assert_eq!(
+            book.amend_scale_order(parent_id, buy_ladder(30, 90, 80)),
+            Ok(parent_id)
+        );
+        let ladder: Vec<(usize, usize)> = book
+            .scale_order(parent_id)
+            .unwrap()
+            .iter()
+            .map(|o| (o.price, o.quantity))
+            .collect();
+        assert_eq!(ladder, vec![(90, 10), (85, 10), (80, 10)]);
+        assert_eq!(book.bids.num_orders, 3);
+
+        let mut other_user = buy_ladder(30, 90, 80);
+        other_user.user_id = 2;
+        assert_eq!(
+            book.amend_scale_order(parent_id, other_user),
+            Err("Scale order belongs to another user".to_string())
+        );
+        assert!(book.cancel_scale_order(2, parent_id).is_empty());
+        assert_eq!(book.scale_order(parent_id).unwrap().len(), 3);
+        assert!(book.orders.values().all(|o| o.user_id == 1));
+    }
+
+    fn min_quantity_order(id: u32, size: usize, min_quantity: usize, tif: TimeInForce) -> Order {
+        let mut order = Order::new(id, 1, Side::Buy, OrderType::Limit, tif, 101, size);
+        order.min_quantity = min_quantity;
+        order
+    }
+
+    #[test]
+    fn test_min_quantity_fills_across_levels() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 101, 2, 2, TimeInForce::GTC);
+        let order = book.add_order(min_quantity_order(3, 10, 5, TimeInForce::IOC));
+        assert!(order.err.is_none());
+        assert_eq!(order.cancelled_quantity, 4);
+        assert_eq!(book.asks.num_orders, 0);
+    }
+
+    #[test]
+    fn test_min_quantity_ioc_rejected() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 102, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_order(min_quantity_order(3, 10, 5, TimeInForce::IOC));
+        assert_eq!(
+            order.err,
+            Some("Minimum quantity cannot be filled".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 3"]);
+        assert_eq!(book.asks.volume, 6);
+    }
+
+    #[test]
+    fn test_min_quantity_gtc_never_rests_crossed() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_order(min_quantity_order(3, 10, 5, TimeInForce::GTC));
+        assert_eq!(
+            order.err,
+            Some("Minimum quantity cannot be filled".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 3"]);
+        assert_eq!(book.asks.volume, 3);
+        assert_eq!(book.bids.volume, 0);
+    }
+
+    #[test]
+    fn test_min_quantity_gtc_rests_below_the_offer() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 102, 2, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_order(min_quantity_order(3, 10, 5, TimeInForce::GTC));
+        assert!(order.err.is_none() && order.done.is_empty());
+        assert_eq!(messages(&rx), vec!["A, 1, 3", "B, B, 101, 10"]);
+        assert_eq!(book.bids.volume, 10);
+    }
+
+    #[test]
+    fn test_min_quantity_larger_than_order_rejected() {
+        let (mut book, _rx) = order_book();
+        let order = book.add_order(min_quantity_order(3, 10, 11, TimeInForce::GTC));
+        assert_eq!(order.err, Some("Invalid minimum quantity".to_string()));
+    }
+
+    #[test]
+    fn test_market_to_limit_rests_remainder() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 101, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_market_to_limit_order(Side::Buy, 10, 1, 3);
+        assert!(order.err.is_none());
+        assert_eq!(order.quantity_left, 4);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 1, 3",
+                "T, 1, 3, 2, 1, 100, 3",
+                "B, S, 101, 3",
+                "T, 1, 3, 2, 2, 101, 3",
+                "B, S, -, -",
+                "A, 1, 3",
+                "B, B, 101, 4"
+            ]
+        );
+        let resting = book.orders.get(&3).unwrap();
+        assert_eq!(resting.order_type, OrderType::Limit);
+        assert_eq!((resting.price, resting.quantity), (101, 4));
+    }
+
+    #[test]
+    fn test_market_to_limit_fully_filled() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 5, 100, 2, 1, TimeInForce::GTC);
+        let order = book.add_market_to_limit_order(Side::Sell, 5, 1, 2);
+        assert_eq!(order.quantity_left, 0);
+        assert!(book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_market_to_limit_rejected_without_liquidity() {
+        let (mut book, rx) = order_book();
+        let order = book.add_market_to_limit_order(Side::Buy, 10, 1, 3);
+        assert_eq!(
+            order.err,
+            Some("No liquidity for market to limit order".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 3"]);
+        assert!(book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_price_protection_stops_market_sweep() {
+        let (mut book, rx) = order_book();
+        book.set_price_protection(Some(PriceProtection::Ticks(2)));
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 102, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 103, 2, 3, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_market_order(Side::Buy, 10, 1, 4);
+        assert_eq!(order.quantity_left, 4);
+        assert_eq!(order.cancelled_quantity, 4);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 1, 4",
+                "T, 1, 4, 2, 1, 100, 3",
+                "B, S, 102, 3",
+                "T, 1, 4, 2, 2, 102, 3",
+                "B, S, 103, 3",
+                "X, 1, 4, 4, PRICE_PROTECTION"
+            ]
+        );
+        assert_eq!(book.asks.volume, 3);
+    }
+
+    #[test]
+    fn test_price_protection_percent() {
+        let (mut book, _rx) = order_book();
+        book.set_price_protection(Some(PriceProtection::Percent(5.0)));
+        book.add_limit_order(Side::Buy, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 3, 95, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 3, 94, 2, 3, TimeInForce::GTC);
+        let order = book.add_market_order(Side::Sell, 10, 1, 4);
+        assert_eq!(order.cancelled_quantity, 4);
+        assert_eq!(book.bids.max_price(), 94);
+    }
+
+    #[test]
+    fn test_without_price_protection_market_clears_book() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Sell, 3, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 3, 200, 2, 2, TimeInForce::GTC);
+        let order = book.add_market_order(Side::Buy, 10, 1, 3);
+        assert_eq!(order.quantity_left, 4);
+        assert_eq!(order.cancelled_quantity, 0);
+        assert_eq!(book.asks.num_orders, 0);
+    }
+
+    #[test]
+    fn test_hidden_order_not_displayed() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 5, 101, 2, 1, TimeInForce::GTC);
+        messages(&rx);
+        book.add_hidden_order(Side::Sell, 10, 100, 2, 2, TimeInForce::GTC);
+        assert_eq!(messages(&rx), vec!["A, 2, 2"]);
+        assert_eq!(book.asks.volume, 5);
+        assert_eq!(book.depth(5).asks, vec![(101, 5)]);
+
+        book.add_limit_order(Side::Buy, 4, 100, 1, 3, TimeInForce::GTC);
+        assert_eq!(messages(&rx), vec!["A, 1, 3", "T, 1, 3, 2, 2, 100, 4"]);
+        assert!(matches!(
+            book.cancel_order_user(2, 2),
+            CancelResult::Cancelled(_)
+        ));
+        assert_eq!(messages(&rx), vec!["A, 2, 2"]);
+    }
+
+    #[test]
+    fn test_hidden_order_queues_behind_displayed() {
+        let (mut book, rx) = order_book();
+        book.add_hidden_order(Side::Sell, 10, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        assert_eq!(book.depth(5).asks, vec![(100, 5)]);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 8, 100, 1, 3, TimeInForce::GTC);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 1, 3",
+                "T, 1, 3, 2, 2, 100, 5",
+                "B, S, -, -",
+                "T, 1, 3, 2, 1, 100, 3"
+            ]
+        );
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 7);
+        assert_eq!(book.asks.volume, 0);
+    }
+
+    #[test]
+    fn test_hidden_iceberg_rejected() {
+        let (mut book, _rx) = order_book();
+        let mut order = Order::new(
+            1,
+            1,
+            Side::Buy,
+            OrderType::Iceberg,
+            TimeInForce::GTC,
+            100,
+            10,
+        );
+        order.peak_quantity = 2;
+        order.hidden = true;
+        let order = book.add_order(order);
+        assert_eq!(
+            order.err,
+            Some("Hidden orders cannot be icebergs".to_string())
+        );
+    }
+
+    #[test]
+    fn test_all_or_none_order_skipped_until_fillable() {
+        let (mut book, rx) = order_book();
+        book.add_all_or_none_order(Side::Sell, 10, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 101, 2, 3, TimeInForce::GTC);
+        messages(&rx);
+
+        book.add_limit_order(Side::Buy, 8, 101, 1, 4, TimeInForce::GTC);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 1, 4",
+                "T, 1, 4, 2, 2, 100, 5",
+                "B, S, 100, 5",
+                "T, 1, 4, 2, 3, 101, 3"
+            ]
+        );
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 10);
+
+        book.add_limit_order(Side::Buy, 12, 100, 1, 5, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.orders.get(&5).unwrap().quantity, 2);
+    }
+
+    #[test]
+    fn test_all_or_none_order_keeps_priority() {
+        let (mut book, _rx) = order_book();
+        book.add_all_or_none_order(Side::Sell, 10, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 3, 100, 1, 3, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 100, 1, 4, TimeInForce::GTC);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 2);
+    }
+
+    #[test]
+    fn test_all_or_none_market_sweep_skips_level() {
+        let (mut book, _rx) = order_book();
+        book.add_all_or_none_order(Side::Buy, 10, 100, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 4, 99, 2, 2, TimeInForce::GTC);
+        let order = book.add_market_order(Side::Sell, 6, 1, 3);
+        assert_eq!(order.quantity_left, 2);
+        assert_eq!(book.bids.num_orders, 1);
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 10);
+    }
+
+    #[test]
+    fn test_incoming_all_or_none_order() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 5, 100, 2, 1, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_all_or_none_order(Side::Buy, 8, 100, 1, 2, TimeInForce::IOC);
+        assert_eq!(
+            order.err,
+            Some("All or none order cannot be filled".to_string())
+        );
+        // it can't rest across the offer either
+        let order = book.add_all_or_none_order(Side::Buy, 8, 100, 1, 3, TimeInForce::GTC);
+        assert_eq!(
+            order.err,
+            Some("All or none order cannot be filled".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 2", "R, 1, 3"]);
+        assert_eq!(book.asks.volume, 5);
+        assert_eq!(book.bids.volume, 0);
+    }
+
+    #[test]
+    fn test_resting_all_or_none_order_fills_once_enough_rests_across() {
+        let (mut book, rx) = order_book();
+        book.add_all_or_none_order(Side::Buy, 8, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&1).unwrap().quantity, 8);
+        assert_eq!(book.asks.volume, 5);
+        messages(&rx);
+
+        book.add_limit_order(Side::Sell, 3, 99, 2, 3, TimeInForce::GTC);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 2, 3",
+                "B, S, 99, 3",
+                "T, 1, 1, 2, 3, 99, 3",
+                "B, S, 100, 3",
+                "T, 1, 1, 2, 2, 100, 5",
+                "B, S, -, -",
+                "B, B, -, -"
+            ]
+        );
+        assert!(book.orders.is_empty());
+        assert_eq!(book.order_status(1), OrderStatus::Filled);
+    }
+
+    fn bracket(entry_size: usize) -> (Order, Order, Order) {
+        let entry = Order::new(
+            1,
+            1,
+            Side::Buy,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            entry_size,
+        );
+        let take_profit = Order::new(2, 1, Side::Sell, OrderType::Limit, TimeInForce::GTC, 110, 0);
+        let mut stop_loss = Order::new(
+            3,
+            1,
+            Side::Sell,
+            OrderType::StopMarket,
+            TimeInForce::GTC,
+            0,
+            0,
+        );
+        stop_loss.stop_price = 90;
+        (entry, take_profit, stop_loss)
+    }
+
+    #[test]
+    fn test_bracket_children_follow_entry_fills() {
+        let (mut book, rx) = order_book();
+        let (entry, take_profit, stop_loss) = bracket(10);
+        let order = book.add_bracket_order(entry, take_profit, stop_loss);
+        assert!(order.err.is_none());
+        assert_eq!(book.orders.len(), 1);
+        messages(&rx);
+
+        book.add_limit_order(Side::Sell, 4, 100, 2, 4, TimeInForce::GTC);
+        let take_profit = book.orders.get(&2).unwrap();
+        assert_eq!((take_profit.quantity, take_profit.parent_id), (4, Some(1)));
+        assert_eq!(take_profit.linked_id, Some(3));
+        assert_eq!(book.orders.get(&3).unwrap().quantity, 4);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "A, 2, 4",
+                "T, 2, 4, 1, 1, 100, 4",
+                "B, B, 100, 6",
+                "A, 1, 2",
+                "B, S, 110, 4",
+                "A, 1, 3"
+            ]
+        );
+
+        book.add_limit_order(Side::Sell, 6, 100, 2, 5, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 10);
+        assert_eq!(book.orders.get(&3).unwrap().quantity, 10);
+        assert!(messages(&rx).contains(&"O, 1, 3, 10".to_string()));
+        assert!(book.brackets.is_empty());
+    }
+
+    #[test]
+    fn test_bracket_children_act_as_oco() {
+        let (mut book, _rx) = order_book();
+        let (entry, take_profit, stop_loss) = bracket(10);
+        book.add_bracket_order(entry, take_profit, stop_loss);
+        book.add_limit_order(Side::Sell, 10, 100, 2, 4, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 110, 2, 5, TimeInForce::GTC);
+        assert!(book.orders.is_empty());
+        assert_eq!(book.stops.num_orders, 0);
+    }
+
+    #[test]
+    fn test_cancel_bracket_entry_drops_unfilled_children() {
+        let (mut book, _rx) = order_book();
+        let (entry, take_profit, stop_loss) = bracket(10);
+        book.add_bracket_order(entry, take_profit, stop_loss);
+        book.add_limit_order(Side::Sell, 4, 100, 2, 4, TimeInForce::GTC);
+        book.cancel_order_user(1, 1);
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 4);
+        assert_eq!(book.orders.get(&3).unwrap().quantity, 4);
+        assert!(book.brackets.is_empty());
+
+        let (mut entry, take_profit, stop_loss) = bracket(10);
+        entry.id = 6;
+        let order = book.add_bracket_order(entry, take_profit, stop_loss);
+        assert_eq!(order.err, Some("Order Already Exists".to_string()));
+    }
+
+    #[test]
+    fn test_bracket_child_ids_are_reserved() {
+        let (mut book, _rx) = order_book();
+        let (entry, take_profit, stop_loss) = bracket(10);
+        book.add_bracket_order(entry, take_profit, stop_loss);
+        let order = book.add_limit_order(Side::Buy, 5, 90, 1, 2, TimeInForce::GTC);
+        assert_eq!(order.err, Some("Order Already Exists".to_string()));
+        let order = book.add_stop_order(Side::Sell, 5, 80, None, 2, 3);
+        assert_eq!(order.err, Some("Order Already Exists".to_string()));
+
+        let (mut entry, take_profit, stop_loss) = bracket(10);
+        entry.id = 6;
+        let order = book.add_bracket_order(entry, take_profit, stop_loss);
+        assert_eq!(order.err, Some("Order Already Exists".to_string()));
+
+        book.add_limit_order(Side::Sell, 4, 100, 2, 4, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 4);
+        assert_eq!(book.orders.get(&3).unwrap().quantity, 4);
+    }
+
+    #[test]
+    fn test_bracket_rearms_missing_leg() {
+        let (mut book, rx) = order_book();
+        let (entry, take_profit, stop_loss) = bracket(10);
+        book.add_bracket_order(entry, take_profit, stop_loss);
+        book.add_limit_order(Side::Sell, 4, 100, 2, 4, TimeInForce::GTC);
+        // the stop leg goes away on its own, the take profit stays
+        book.cancel_order(3);
+        messages(&rx);
+
+        book.add_limit_order(Side::Sell, 6, 100, 2, 5, TimeInForce::GTC);
+        assert_eq!(book.orders.get(&2).unwrap().quantity, 10);
+        let stop_loss = book.orders.get(&3).unwrap();
+        assert_eq!((stop_loss.quantity, stop_loss.linked_id), (10, Some(2)));
+        let messages = messages(&rx);
+        assert!(messages.contains(&"O, 1, 2, 10".to_string()));
+        assert!(messages.contains(&"A, 1, 3".to_string()));
+    }
+
+    #[test]
+    fn test_bracket_children_must_exit_the_entry() {
+        let (mut book, _rx) = order_book();
+        let (entry, mut take_profit, stop_loss) = bracket(10);
+        take_profit.side = Side::Buy;
+        let order = book.add_bracket_order(entry, take_profit, stop_loss);
+        assert_eq!(order.err, Some("Invalid bracket child orders".to_string()));
+        assert!(book.orders.is_empty());
+    }
+
+    fn quote(bid_price: usize, bid_size: usize, ask_price: usize, ask_size: usize) -> Quote {
+        Quote {
+            user_id: 7,
+            bid_price,
+            bid_size,
+            ask_price,
+            ask_size,
+        }
+    }
+
+    #[test]
+    fn test_quote_single_acknowledgement() {
+        let (mut book, rx) = order_book();
+        let (bid, ask) = book.add_quote(quote(99, 10, 101, 10));
+        assert!(bid.err.is_none() && ask.err.is_none());
+        let (bid_id, ask_id) = book.quotes[&7];
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "B, B, 99, 10".to_string(),
+                "B, S, 101, 10".to_string(),
+                format!("Q, 7, {:?}, {:?}", bid_id, ask_id)
+            ]
+        );
+        assert_eq!(book.depth(1).bids, vec![(99, 10)]);
+        assert_eq!(book.depth(1).asks, vec![(101, 10)]);
+    }
+
+    #[test]
+    fn test_quote_replaces_previous_quote() {
+        let (mut book, rx) = order_book();
+        book.add_quote(quote(99, 10, 101, 10));
+        let (bid_id, ask_id) = book.quotes[&7];
+        book.add_limit_order(Side::Buy, 5, 99, 1, 1, TimeInForce::GTC);
+        messages(&rx);
+
+        // the bid shrinks in place and stays ahead of order 1, the ask moves
+        book.add_quote(quote(99, 4, 102, 10));
+        assert_eq!(book.bids.num_orders, 2);
+        assert_eq!(book.asks.num_orders, 1);
+        assert_eq!(book.orders[&ask_id].price, 102);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "B, B, 99, 9".to_string(),
+                "B, S, -, -".to_string(),
+                "B, S, 102, 10".to_string(),
+                format!("Q, 7, {:?}, {:?}", bid_id, ask_id)
+            ]
+        );
+        book.add_market_order(Side::Sell, 4, 2, 2);
+        assert!(!book.orders.contains_key(&bid_id));
+        assert_eq!(book.orders[&1].quantity, 5);
+
+        book.add_quote(quote(0, 0, 0, 0));
+        assert!(book.quotes.is_empty());
+        assert_eq!(book.asks.num_orders, 0);
+    }
+
+    #[test]
+    fn test_quote_legs_validated_together() {
+        let (mut book, _rx) = order_book();
+        let (bid, ask) = book.add_quote(quote(101, 10, 101, 10));
+        assert_eq!(bid.err, Some("Quote bid must be below its ask".to_string()));
+        assert_eq!(ask.err, bid.err);
+        let (bid, _) = book.add_quote(quote(0, 10, 101, 10));
+        assert_eq!(bid.err, Some("Invalid quote price".to_string()));
+        assert!(book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_edit_order_reduce_keeps_priority() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 100, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.edit_order(1, 1, 4, 100);
+        assert!(order.err.is_none());
+        assert_eq!(messages(&rx), vec!["A, 1, 1", "B, B, 100, 14"]);
+        assert_eq!(book.bids.volume, 14);
+        assert_eq!(book.bids.total, 1400);
+
+        book.add_market_order(Side::Sell, 4, 3, 3);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.orders[&2].quantity, 10);
+    }
+
+    #[test]
+    fn test_edit_order_increase_loses_priority() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 100, 2, 2, TimeInForce::GTC);
+        book.edit_order(1, 1, 12, 100);
+        assert_eq!(book.bids.volume, 22);
+        book.add_market_order(Side::Sell, 10, 3, 3);
+        assert!(!book.orders.contains_key(&2));
+        assert_eq!(book.orders[&1].quantity, 12);
+    }
+
+    #[test]
+    fn test_edit_order_price_change_may_trade() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 5, 101, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 1, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.edit_order(1, 2, 10, 101);
+        assert_eq!(order.quantity_left, 5);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "B, B, -, -",
+                "A, 1, 2",
+                "T, 1, 2, 2, 1, 101, 5",
+                "B, S, -, -",
+                "A, 1, 2",
+                "B, B, 101, 5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_order_rejected() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        messages(&rx);
+        assert_eq!(
+            book.edit_order(1, 2, 5, 100).err,
+            Some("Order not found".to_string())
+        );
+        assert_eq!(
+            book.edit_order(2, 1, 5, 100).err,
+            Some("Order belongs to another user".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 2", "R, 2, 1"]);
+        assert_eq!(book.orders[&1].quantity, 10);
+    }
+
+    #[test]
+    fn test_rejected_edit_keeps_order() {
+        let mut book = OrderBook::new("TEST".to_string(), false, None);
+        book.add_limit_order(Side::Sell, 10, 101, 2, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 1, 2, TimeInForce::GTC);
+        assert_eq!(
+            book.edit_order(1, 2, 10, 101).err,
+            Some("Trading is not enabled".to_string())
+        );
+        assert_eq!(book.order(2).unwrap().price, 99);
+        assert_eq!(book.bids.volume, 10);
+    }
+
+    #[test]
+    fn test_edit_iceberg_order_reduces_reserve_first() {
+        let (mut book, _rx) = order_book();
+        book.add_iceberg_order(Side::Sell, 30, 100, 10, 2, 1);
+        book.edit_order(2, 1, 15, 100);
+        let order = &book.orders[&1];
+        assert_eq!((order.quantity, order.reserve_quantity), (10, 5));
+    }
+
+    #[test]
+    fn test_cancel_order_user_results() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 1, 2, TimeInForce::GTC);
+        book.add_market_order(Side::Sell, 10, 2, 3);
+        messages(&rx);
+
+        assert_eq!(book.cancel_order_user(2, 2), CancelResult::NotOwner);
+        assert_eq!(book.cancel_order_user(1, 1), CancelResult::AlreadyFilled);
+        assert_eq!(book.cancel_order_user(2, 3), CancelResult::AlreadyFilled);
+        assert_eq!(book.cancel_order_user(1, 9), CancelResult::UnknownOrder);
+        assert_eq!(
+            messages(&rx),
+            vec![
+                "CR, 2, 2, NOT_OWNER",
+                "CR, 1, 1, ALREADY_FILLED",
+                "CR, 2, 3, ALREADY_FILLED",
+                "CR, 1, 9, UNKNOWN_ORDER"
+            ]
+        );
+        assert_eq!(book.bids.volume, 10);
+
+        let removed_order = book.orders[&2].clone();
+        assert_eq!(
+            book.cancel_order_user(1, 2),
+            CancelResult::Cancelled(removed_order)
+        );
+        assert_eq!(book.cancel_order_user(1, 2), CancelResult::UnknownOrder);
+    }
+
+    #[test]
+    fn test_cancel_user_orders() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 3, TimeInForce::GTC);
+        book.add_stop_order(Side::Sell, 10, 90, None, 1, 4);
+        messages(&rx);
+
+        let cancelled: Vec<u32> = book.cancel_user_orders(1).iter().map(|o| o.id).collect();
+        assert_eq!(cancelled, vec![1, 3, 4]);
+        assert_eq!(messages(&rx), vec!["M, 3", "B, B, 99, 10", "B, S, -, -"]);
+        assert_eq!(book.orders.len(), 1);
+        assert_eq!(book.stops.num_orders, 0);
+    }
+
+    #[test]
+    fn test_cancel_side_orders() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 101, 1, 3, TimeInForce::GTC);
+        messages(&rx);
+        assert_eq!(book.cancel_side_orders(Side::Buy).len(), 2);
+        assert_eq!(messages(&rx), vec!["M, 2", "B, B, -, -"]);
+        assert_eq!(book.asks.num_orders, 1);
+    }
+
+    #[test]
+    fn test_cancel_price_range() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Sell, 10, 101, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 102, 2, 2, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 10, 105, 1, 3, TimeInForce::GTC);
+        messages(&rx);
+        let cancelled: Vec<u32> = book
+            .cancel_price_range(Side::Sell, 102, 110)
+            .iter()
+            .map(|o| o.id)
+            .collect();
+        assert_eq!(cancelled, vec![2, 3]);
+        // the top of book did not move
+        assert_eq!(messages(&rx), vec!["M, 2"]);
+        assert_eq!(book.asks.volume, 10);
+    }
+
+    #[test]
+    fn test_cancel_replace() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 100, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.cancel_replace(1, 1, 3, 5, 100, TimeInForce::GTC);
+        assert!(order.err.is_none());
+        assert_eq!(
+            messages(&rx),
+            vec!["RP, 1, 1, 3", "A, 1, 3", "B, B, 100, 15"]
+        );
+        assert!(!book.knows_order(1));
+        // the replacement queues behind order 2
+        book.add_market_order(Side::Sell, 10, 3, 4);
+        assert_eq!(book.orders[&3].quantity, 5);
+        messages(&rx);
+
+        // moving the best order behind the top still reports the side once
+        book.add_limit_order(Side::Buy, 10, 99, 2, 5, TimeInForce::GTC);
+        messages(&rx);
+        book.cancel_replace(1, 3, 6, 5, 98, TimeInForce::GTC);
+        assert_eq!(
+            messages(&rx),
+            vec!["RP, 1, 3, 6", "A, 1, 6", "B, B, 99, 10"]
+        );
+    }
+
+    #[test]
+    fn test_cancel_replace_rejected() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 99, 1, 2, TimeInForce::GTC);
+        book.add_market_order(Side::Sell, 10, 2, 3);
+        messages(&rx);
+        assert_eq!(
+            book.cancel_replace(1, 1, 4, 10, 100, TimeInForce::GTC).err,
+            Some("Order already filled".to_string())
+        );
+        assert_eq!(
+            book.cancel_replace(1, 2, 2, 10, 100, TimeInForce::GTC).err,
+            Some("Order Already Exists".to_string())
+        );
+        assert_eq!(messages(&rx), vec!["R, 1, 4", "R, 1, 2"]);
+        assert_eq!(book.orders[&2].price, 99);
+
+        // not enough depth for the fill or kill replacement
+        assert_eq!(
+            book.cancel_replace(1, 2, 5, 20, 99, TimeInForce::FOK).err,
+            Some("Fill or kill order cannot be filled".to_string())
+        );
+        assert_eq!(book.order(2).unwrap().quantity, 10);
+        assert!(book.order(5).is_none());
+        assert_eq!(messages(&rx), vec!["R, 1, 5"]);
+    }
+
+    #[test]
+    fn test_order_queries() {
+        let (mut book, _rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 100, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Buy, 10, 100, 2, 2, TimeInForce::GTC);
+        book.add_hidden_order(Side::Buy, 10, 100, 1, 3, TimeInForce::GTC);
+        book.add_stop_order(Side::Sell, 10, 90, None, 1, 4);
+        book.add_market_order(Side::Sell, 15, 3, 5);
+
+        assert_eq!(book.order(2).unwrap().quantity, 5);
+        assert_eq!(book.order_status(1), OrderStatus::Filled);
+        assert_eq!(book.order_status(9), OrderStatus::Unknown);
+        let ids: Vec<u32> = book.open_orders(1).iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![3, 4]);
+        assert!(book.open_orders(3).is_empty());
+        let queue: Vec<(usize, u32)> = book
+            .orders_at_price(Side::Buy, 100)
+            .iter()
+            .map(|(position, o)| (*position, o.id))
+            .collect();
+        assert_eq!(queue, vec![(1, 2), (2, 3)]);
+
+        book.cancel_user_orders(1);
+        assert!(book.open_orders(1).is_empty());
+        assert!(!book.user_orders.contains_key(&1));
+    }
+
+    fn client_order(side: Side, size: usize, price: usize, user_id: u32) -> Order {
+        Order::new(
+            0,
+            user_id,
+            side,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            price,
+            size,
+        )
+    }
+
+    #[test]
+    fn test_submit_order_assigns_ids() {
+        let (mut book, rx) = order_book();
+        let (first, order) = book.submit_order(client_order(Side::Buy, 10, 100, 1), "abc");
+        assert!(order.err.is_none());
+        let first = first.unwrap();
+        // client order ids are per user
+        let (second, order) = book.submit_order(client_order(Side::Buy, 10, 99, 2), "abc");
+        assert!(order.err.is_none());
+        let second = second.unwrap();
+        assert_ne!(first, second);
+        let (third, order) = book.submit_order(client_order(Side::Buy, 10, 98, 1), "abc");
+        assert_eq!(third, None);
+        assert_eq!(
+            order.err,
+            Some("Client Order Id Already Exists".to_string())
+        );
+        assert_eq!(
+            messages(&rx),
+            vec![
+                format!("A, 1, {}, abc", first),
+                "B, B, 100, 10".to_string(),
+                format!("A, 2, {}, abc", second),
+                "R, 1, -, abc".to_string(),
+            ]
+        );
+        assert_eq!(book.find_order(1, "abc").unwrap().id, first);
+        assert_eq!(book.find_order(2, first), None);
+
+        book.add_market_order(Side::Sell, 10, 3, 1);
+        assert_eq!(
+            messages(&rx)[1],
+            format!("T, 3, 1, 1, {}, 100, 10, , abc", first)
+        );
+        // the ClOrdID of a filled order may be used again
+        let (_, order) = book.submit_order(client_order(Side::Buy, 10, 98, 1), "abc");
+        assert!(order.err.is_none());
+    }
+
+    #[test]
+    fn test_amend_and_cancel_by_client_order_id() {
+        let (mut book, rx) = order_book();
+        let id = book
+            .submit_order(client_order(Side::Buy, 10, 100, 1), "abc")
+            .0
+            .unwrap();
+        messages(&rx);
+        book.edit_order(1, "abc", 5, 100);
+        assert_eq!(book.orders[&id].quantity, 5);
+        assert_eq!(
+            book.edit_order(1, "xyz", 5, 100).err,
+            Some("Order not found".to_string())
+        );
+        assert_eq!(
+            messages(&rx),
+            vec![
+                format!("A, 1, {}, abc", id),
+                "B, B, 100, 5".to_string(),
+                "R, 1, -, xyz".to_string()
+            ]
+        );
+
+        book.cancel_replace(1, "abc", "def", 8, 101, TimeInForce::GTC);
+        let new_id = book.find_order(1, "def").unwrap().id;
+        assert_ne!(new_id, id);
+        assert_eq!(
+            messages(&rx)[0],
+            format!("RP, 1, {}, {}, abc, def", id, new_id)
+        );
+        assert_eq!(book.cancel_order_user(1, "abc"), CancelResult::UnknownOrder);
+        assert!(matches!(
+            book.cancel_order_user(1, "def"),
+            CancelResult::Cancelled(order) if order.id == new_id
+        ));
+    }
+
+    #[test]
+    fn test_done_order_records_are_bounded() {
+        let (mut book, _rx) = order_book();
+        let filled_id = book
+            .submit_order(client_order(Side::Buy, 10, 100, 1), "abc")
+            .0
+            .unwrap();
+        book.add_market_order(Side::Sell, 10, 2, 1);
+        let open_id = book
+            .submit_order(client_order(Side::Buy, 5, 90, 1), "def")
+            .0
+            .unwrap();
+        book.edit_order(1, "def", 8, 90);
+        // only the filled order and the market order are done, the amend is not
+        assert_eq!(book.done_orders, [filled_id, 1]);
+        assert_eq!(book.order_status(filled_id), OrderStatus::Filled);
+        assert_eq!(
+            book.cancel_order_user(1, "abc"),
+            CancelResult::AlreadyFilled
+        );
+
+        for id in 0..DONE_ORDERS_KEPT as u32 {
+            book.add_limit_order(Side::Sell, 1, 200, 2, 1000 + id, TimeInForce::IOC);
+        }
+        assert_eq!(book.done_orders.len(), DONE_ORDERS_KEPT);
+        assert_eq!(book.order_status(filled_id), OrderStatus::Unknown);
+        assert_eq!(book.cancel_order_user(1, "abc"), CancelResult::UnknownOrder);
+        assert!(!book.filled_orders.contains(&filled_id));
+        // the open order keeps its ClOrdID
+        assert_eq!(book.client_ids.len(), 1);
+        assert_eq!(book.find_order(1, "def").unwrap().id, open_id);
+    }
+
+    #[test]
+    fn test_market_to_limit_order_duplicate_id() {
+        let (mut book, rx) = order_book();
+        book.add_limit_order(Side::Buy, 10, 99, 1, 1, TimeInForce::GTC);
+        book.add_limit_order(Side::Sell, 5, 100, 2, 2, TimeInForce::GTC);
+        messages(&rx);
+        let order = book.add_market_to_limit_order(Side::Buy, 10, 3, 1);
+        assert_eq!(order.err, Some("Order Already Exists".to_string()));
+        assert!(messages(&rx).is_empty());
+        assert_eq!(book.orders[&1].user_id, 1);
+        assert_eq!(book.asks.volume, 5);
+    }
+
+    #[test]
+    fn test_iceberg_refill_behind_all_or_none_level() {
+        let (mut book, rx) = order_book();
+        book.add_all_or_none_order(Side::Sell, 50, 100, 1, 1, TimeInForce::GTC);
+        book.add_iceberg_order(Side::Sell, 30, 101, 10, 2, 2);
+        messages(&rx);
+        book.add_limit_order(Side::Buy, 10, 101, 3, 3, TimeInForce::IOC);
+        assert_eq!(messages(&rx), vec!["A, 3, 3", "T, 3, 3, 2, 2, 101, 10"]);
+        assert_eq!(book.orders[&2].quantity, 10);
+    }
+
+    #[test]
+    fn test_quote_moves_without_trading_with_itself() {
+        let (mut book, rx) = order_book();
+        book.add_quote(quote(100, 10, 101, 10));
+        messages(&rx);
+        book.add_quote(quote(101, 10, 102, 10));
+        assert!(messages(&rx).iter().all(|msg| !msg.starts_with("T, ")));
+        assert_eq!(book.depth(1).bids, vec![(101, 10)]);
+        assert_eq!(book.depth(1).asks, vec![(102, 10)]);
+
+        let mut book = OrderBook::new("TEST".to_string(), false, None);
+        book.add_quote(quote(100, 10, 101, 10));
+        let (bid, ask) = book.add_quote(quote(101, 10, 102, 10));
+        assert!(bid.err.is_none() && ask.err.is_none());
+    }
+
+    #[test]
+    fn test_quote_increase_loses_priority() {
+        let (mut book, _rx) = order_book();
+        book.add_quote(quote(99, 1, 101, 10));
+        book.add_limit_order(Side::Buy, 10, 99, 1, 1, TimeInForce::GTC);
+        book.add_quote(quote(99, 1000, 101, 10));
+        book.add_market_order(Side::Sell, 10, 2, 2);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.depth(1).bids, vec![(99, 1000)]);
+    }
+}
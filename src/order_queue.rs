@@ -5,6 +5,8 @@ use crate::order::Order;
 pub struct OrderQueue {
     pub price: usize,
     pub volume: usize,
+    // quantity that is not displayed in `volume`, e.g. iceberg reserves
+    pub hidden_volume: usize,
     orders: Vec<Order>,
 }
 
@@ -13,22 +15,45 @@ impl OrderQueue {
         Self {
             price,
             volume: 0,
+            hidden_volume: 0,
             orders: Vec::new(),
         }
     }
 
-    // adds order to tail of the queue and returns the order
+    // displayed and hidden quantity of an order
+    fn volumes(order: &Order) -> (usize, usize) {
+        if order.hidden {
+            (0, order.quantity + order.reserve_quantity)
+        } else {
+            (order.quantity, order.reserve_quantity)
+        }
+    }
+
+    // adds order to tail of the queue and returns the order, displayed orders
+    // always stay ahead of hidden ones
     pub fn append(&mut self, order: Order) -> &Order {
-        self.volume += order.quantity;
-        self.orders.push(order);
-        self.orders.last().unwrap()
+        let (volume, hidden_volume) = Self::volumes(&order);
+        self.volume += volume;
+        self.hidden_volume += hidden_volume;
+        let index = if order.hidden {
+            self.orders.len()
+        } else {
+            self.orders
+                .iter()
+                .position(|x| x.hidden)
+                .unwrap_or(self.orders.len())
+        };
+        self.orders.insert(index, order);
+        &self.orders[index]
     }
 
     // removes order from the queue and returns the order
     pub fn remove(&mut self, order: &Order) -> Option<Order> {
         if let Some(index) = self.orders.iter().position(|x| x.id == order.id) {
             let order = self.orders.remove(index);
-            self.volume -= order.quantity;
+            let (volume, hidden_volume) = Self::volumes(&order);
+            self.volume -= volume;
+            self.hidden_volume -= hidden_volume;
             Some(order)
         } else {
             None
@@ -39,14 +64,47 @@ impl OrderQueue {
         self.orders.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // orders in queue (time priority) order
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter()
+    }
+
+    #[allow(dead_code)]
     pub fn head(&self) -> Option<&Order> {
         self.orders.first()
     }
 
+    // first order in the queue that `quantity` can trade with, all or none
+    // orders only count when they can be filled completely
+    pub fn first_fillable(&self, quantity: usize) -> Option<&Order> {
+        self.orders
+            .iter()
+            .find(|order| !order.all_or_none || order.quantity <= quantity)
+    }
+
+    // replaces the order with the same id, keeping its position in the queue
+    pub fn update(&mut self, new_order: Order) -> Option<Order> {
+        let index = self.orders.iter().position(|x| x.id == new_order.id)?;
+        let old_order = std::mem::replace(&mut self.orders[index], new_order);
+        self.replace_volumes(&old_order, index);
+        Some(old_order)
+    }
+
+    #[allow(dead_code)]
     pub fn update_head(&mut self, old_order: Order, new_order: Order) {
-        self.volume -= old_order.quantity;
-        self.volume += new_order.quantity;
         self.orders[0] = new_order;
+        self.replace_volumes(&old_order, 0);
+    }
+
+    fn replace_volumes(&mut self, old_order: &Order, index: usize) {
+        let (old_volume, old_hidden_volume) = Self::volumes(old_order);
+        let (volume, hidden_volume) = Self::volumes(&self.orders[index]);
+        self.volume = self.volume - old_volume + volume;
+        self.hidden_volume = self.hidden_volume - old_hidden_volume + hidden_volume;
     }
 }
 
@@ -305,4 +363,81 @@ mod tests {
         assert_eq!(queue.len(), 2);
         assert_eq!(queue.head().unwrap().quantity, 200);
     }
+
+    #[test]
+    fn test_hidden_volume() {
+        let mut queue = OrderQueue::new(100);
+        let mut order = Order::new(
+            1,
+            1,
+            Side::Buy,
+            OrderType::Iceberg,
+            TimeInForce::GTC,
+            100,
+            10,
+        );
+        order.peak_quantity = 10;
+        order.reserve_quantity = 90;
+        queue.append(order.clone());
+        assert_eq!(queue.volume, 10);
+        assert_eq!(queue.hidden_volume, 90);
+        queue.remove(&order);
+        assert_eq!(queue.volume, 0);
+        assert_eq!(queue.hidden_volume, 0);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut queue = OrderQueue::new(100);
+        for id in 1..=3 {
+            queue.append(Order::new(
+                id,
+                1,
+                Side::Buy,
+                OrderType::Limit,
+                TimeInForce::GTC,
+                100,
+                100,
+            ));
+        }
+        let mut order = Order::new(2, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 100, 40);
+        assert_eq!(queue.update(order.clone()).unwrap().quantity, 100);
+        assert_eq!(queue.volume, 240);
+        assert_eq!(queue.iter().nth(1).unwrap().quantity, 40);
+        order.id = 4;
+        assert!(queue.update(order).is_none());
+    }
+
+    #[test]
+    fn test_hidden_orders_queue_behind_displayed() {
+        let mut queue = OrderQueue::new(100);
+        let mut hidden = Order::new(1, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 100, 30);
+        hidden.hidden = true;
+        queue.append(hidden);
+        let displayed = Order::new(2, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 100, 10);
+        queue.append(displayed);
+        assert_eq!(queue.head().unwrap().id, 2);
+        assert_eq!(queue.volume, 10);
+        assert_eq!(queue.hidden_volume, 30);
+
+        let head = queue.head().unwrap().clone();
+        queue.remove(&head);
+        let mut hidden = queue.head().unwrap().clone();
+        hidden.quantity = 20;
+        queue.update(hidden);
+        assert_eq!(queue.volume, 0);
+        assert_eq!(queue.hidden_volume, 20);
+    }
+
+    #[test]
+    fn test_first_fillable() {
+        let mut queue = OrderQueue::new(100);
+        let mut order = Order::new(1, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 100, 10);
+        order.all_or_none = true;
+        queue.append(order);
+        let order = Order::new(2, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 100, 10);
+        queue.append(order);
+        assert_eq!(queue.first_fillable(5).unwrap().id, 2);
+        assert_eq!(queue.first_fillable(10).unwrap().id, 1);
+    }
 }
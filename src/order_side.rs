@@ -39,52 +39,42 @@ impl OrderSide {
         }
     }
 
+    // side volume and total only count what is displayed
+    fn displayed_quantity(order: &Order) -> usize {
+        if order.hidden {
+            0
+        } else {
+            order.quantity
+        }
+    }
+
     pub fn add_order(&mut self, order: Order) -> Order {
         let price = order.price;
-        let quantity = order.quantity;
+        let quantity = Self::displayed_quantity(&order);
         let order_queue = self.prices.entry(price).or_insert(OrderQueue::new(price));
         order_queue.append(order.clone());
         self.volume += quantity;
         self.total += price * quantity;
         self.num_orders += 1;
-        return order;
+        order
     }
 
     pub fn remove_order(&mut self, order: &Order, tx: &Option<Sender<String>>) -> Option<Order> {
-        let price = order.price;
-        let quantity = order.quantity;
-        let order_queue = self.prices.get_mut(&price)?;
-        let removed_order = order_queue.remove(order)?;
+        let removed_order = self.take_order(order)?;
         OrderSide::log(
             tx,
-            format!("A, {:?}, {:?}", removed_order.user_id, removed_order.id),
+            format!(
+                "A, {:?}, {:?}{}",
+                removed_order.user_id,
+                removed_order.id,
+                removed_order.client_order_id.as_ref().map_or(
+                    String::new(),
+                    |client_order_id| format!(", {}", client_order_id)
+                )
+            ),
         );
-        self.volume -= quantity;
-        self.total -= price * quantity;
-        self.num_orders -= 1;
-        if order_queue.len() <= 0 {
-            self.prices.remove(&price);
-        }
-        if self.side == Side::Buy {
-            let p = self.max_price();
-            if price >= p {
-                if self.volume == 0 {
-                    OrderSide::log(tx, format!("B, B, -, -"));
-                } else {
-                    OrderSide::log(tx, format!("B, B, {:?}, {:?}", p, quantity));
-                }
-            }
-        } else {
-            let p = self.min_price();
-            if price <= p {
-                if self.volume == 0 {
-                    OrderSide::log(tx, format!("B, S, -, -"));
-                } else {
-                    OrderSide::log(tx, format!("B, S, {:?}, {:?}", p, quantity));
-                }
-            }
-        };
-        return Some(removed_order);
+        self.log_top_of_book(&removed_order, tx);
+        Some(removed_order)
     }
 
     pub fn remove_order_internal(
@@ -92,55 +82,212 @@ impl OrderSide {
         order: &Order,
         tx: &Option<Sender<String>>,
     ) -> Option<Order> {
+        let removed_order = self.take_order(order)?;
+        self.log_top_of_book(&removed_order, tx);
+        Some(removed_order)
+    }
+
+    // removes an order without reporting anything
+    pub fn take_order(&mut self, order: &Order) -> Option<Order> {
         let price = order.price;
-        let quantity = order.quantity;
+        let quantity = Self::displayed_quantity(order);
         let order_queue = self.prices.get_mut(&price)?;
         let removed_order = order_queue.remove(order)?;
         self.volume -= quantity;
         self.total -= price * quantity;
         self.num_orders -= 1;
-        if order_queue.len() <= 0 {
+        if order_queue.is_empty() {
             self.prices.remove(&price);
         }
+        Some(removed_order)
+    }
+
+    fn log_top_of_book(&self, removed_order: &Order, tx: &Option<Sender<String>>) {
+        if removed_order.hidden {
+            return;
+        }
+        let price = removed_order.price;
+        let quantity = removed_order.quantity;
         if self.side == Side::Buy {
-            let p = self.max_price();
+            let p = self.displayed_price_queue().map_or(0, |queue| queue.price);
             if price >= p {
                 if self.volume == 0 {
-                    OrderSide::log(tx, format!("B, B, -, -"));
+                    OrderSide::log(tx, "B, B, -, -".to_string());
                 } else {
                     OrderSide::log(tx, format!("B, B, {:?}, {:?}", p, quantity));
                 }
             }
         } else {
-            let p = self.min_price();
+            let p = self
+                .displayed_price_queue()
+                .map_or(usize::MAX, |queue| queue.price);
             if price <= p {
                 if self.volume == 0 {
-                    OrderSide::log(tx, format!("B, S, -, -"));
+                    OrderSide::log(tx, "B, S, -, -".to_string());
                 } else {
                     OrderSide::log(tx, format!("B, S, {:?}, {:?}", p, quantity));
                 }
             }
         };
-        return Some(removed_order);
+    }
+
+    // use when the visible slice of an iceberg order was filled, the order
+    // goes to the back of its level with a new slice taken from the reserve
+    pub fn refill_order(&mut self, order: &Order) -> Option<Order> {
+        let order_queue = self.prices.get_mut(&order.price)?;
+        let mut refilled_order = order_queue.remove(order)?;
+        self.volume -= refilled_order.quantity;
+        self.total -= refilled_order.price * refilled_order.quantity;
+        let slice = refilled_order
+            .peak_quantity
+            .min(refilled_order.reserve_quantity);
+        refilled_order.quantity = slice;
+        refilled_order.reserve_quantity -= slice;
+        order_queue.append(refilled_order.clone());
+        self.volume += slice;
+        self.total += refilled_order.price * slice;
+        Some(refilled_order)
+    }
+
+    // takes `quantity` off a resting order without touching its queue position,
+    // iceberg reserves are used up before the visible slice
+    pub fn reduce_order(&mut self, order: &Order, quantity: usize) -> Option<Order> {
+        let order_queue = self.prices.get_mut(&order.price)?;
+        let mut reduced_order = order_queue.iter().find(|x| x.id == order.id)?.clone();
+        let from_reserve = quantity.min(reduced_order.reserve_quantity);
+        let from_visible = (quantity - from_reserve).min(reduced_order.quantity);
+        reduced_order.reserve_quantity -= from_reserve;
+        reduced_order.quantity -= from_visible;
+        order_queue.update(reduced_order.clone());
+        if !reduced_order.hidden {
+            self.volume -= from_visible;
+            self.total -= reduced_order.price * from_visible;
+        }
+        Some(reduced_order)
+    }
+
+    // adds `quantity` to the visible part of a resting order in place
+    pub fn increase_order(&mut self, order: &Order, quantity: usize) -> Option<Order> {
+        let order_queue = self.prices.get_mut(&order.price)?;
+        let mut increased_order = order_queue.iter().find(|x| x.id == order.id)?.clone();
+        increased_order.quantity += quantity;
+        order_queue.update(increased_order.clone());
+        if !increased_order.hidden {
+            self.volume += quantity;
+            self.total += increased_order.price * quantity;
+        }
+        Some(increased_order)
     }
 
     // use when a trade is executed and order is partially filled
     pub fn decrease_volume_and_total(&mut self, order: &Order) {
         let price = order.price;
-        let quantity = order.quantity;
+        let quantity = Self::displayed_quantity(order);
         self.volume -= quantity;
         self.total -= price * quantity;
     }
 
+    // walks the levels from the best price towards `price` (inclusive) and sums
+    // their volume, stopping as soon as `quantity` is covered. All or none orders
+    // bigger than what is still needed are left out, matching skips them too
+    pub fn available_volume(&self, price: usize, quantity: usize) -> usize {
+        let levels: Box<dyn Iterator<Item = &OrderQueue>> = match self.side {
+            Side::Buy => Box::new(self.prices.range(price..).rev().map(|(_, q)| q)),
+            Side::Sell => Box::new(self.prices.range(..=price).map(|(_, q)| q)),
+        };
+        let mut available = 0;
+        for order_queue in levels {
+            for order in order_queue.iter() {
+                let size = order.quantity + order.reserve_quantity;
+                if order.all_or_none && size > quantity - available {
+                    continue;
+                }
+                available += size;
+                if available >= quantity {
+                    return available;
+                }
+            }
+        }
+        available
+    }
+
+    // first level after `price` in matching order, or the best level
+    pub fn next_price_queue(&self, price: Option<usize>) -> Option<&OrderQueue> {
+        let (price, mut levels): (usize, Box<dyn Iterator<Item = &OrderQueue>>) =
+            match (self.side, price) {
+                (_, None) => {
+                    return match self.side {
+                        Side::Buy => self.max_price_queue(),
+                        Side::Sell => self.min_price_queue(),
+                    }
+                }
+                (Side::Buy, Some(price)) => (
+                    price,
+                    Box::new(self.prices.range(..price).rev().map(|(_, q)| q)),
+                ),
+                (Side::Sell, Some(price)) => {
+                    (price, Box::new(self.prices.range(price..).map(|(_, q)| q)))
+                }
+            };
+        levels.find(|order_queue| order_queue.price != price)
+    }
+
+    pub fn price_queue(&self, price: usize) -> Option<&OrderQueue> {
+        self.prices.get(&price)
+    }
+
+    pub fn price_queue_mut(&mut self, price: usize) -> Option<&mut OrderQueue> {
+        self.prices.get_mut(&price)
+    }
+
+    // best price backed by at least one displayed order that is not pegged, so
+    // pegged orders never use themselves as their reference and never give
+    // away the price of a hidden order
+    pub fn reference_price(&self) -> Option<usize> {
+        let mut levels: Box<dyn Iterator<Item = &OrderQueue>> = match self.side {
+            Side::Buy => Box::new(self.prices.values().rev()),
+            Side::Sell => Box::new(self.prices.values()),
+        };
+        levels
+            .find(|order_queue| {
+                order_queue
+                    .iter()
+                    .any(|order| order.peg.is_none() && !order.hidden)
+            })
+            .map(|order_queue| order_queue.price)
+    }
+
+    // best level with displayed volume, levels holding only hidden orders are skipped
+    pub fn displayed_price_queue(&self) -> Option<&OrderQueue> {
+        self.depth_levels().next()
+    }
+
+    // best `levels` displayed price levels as (price, volume)
+    pub fn depth(&self, levels: usize) -> Vec<(usize, usize)> {
+        self.depth_levels()
+            .take(levels)
+            .map(|order_queue| (order_queue.price, order_queue.volume))
+            .collect()
+    }
+
+    fn depth_levels(&self) -> Box<dyn Iterator<Item = &OrderQueue> + '_> {
+        let levels: Box<dyn Iterator<Item = &OrderQueue>> = match self.side {
+            Side::Buy => Box::new(self.prices.values().rev()),
+            Side::Sell => Box::new(self.prices.values()),
+        };
+        Box::new(levels.filter(|order_queue| order_queue.volume > 0))
+    }
+
     pub fn max_price_queue(&self) -> Option<&OrderQueue> {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let (_k, v) = self.prices.iter().next_back().unwrap();
             return Some(v);
         }
         None
     }
+    #[allow(dead_code)]
     pub fn max_price_queue_mut(&mut self) -> Option<&mut OrderQueue> {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let (_k, v) = self.prices.iter_mut().next_back().unwrap();
             return Some(v);
         }
@@ -148,14 +295,15 @@ impl OrderSide {
     }
 
     pub fn min_price_queue(&self) -> Option<&OrderQueue> {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let min = self.prices.iter().next().unwrap();
             return Some(min.1);
         }
         None
     }
+    #[allow(dead_code)]
     pub fn min_price_queue_mut(&mut self) -> Option<&mut OrderQueue> {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let min = self.prices.iter_mut().next().unwrap();
             return Some(min.1);
         }
@@ -163,7 +311,7 @@ impl OrderSide {
     }
 
     pub fn max_price(&self) -> usize {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let (k, _v) = self.prices.iter().next_back().unwrap();
             return *k;
         }
@@ -171,7 +319,7 @@ impl OrderSide {
     }
 
     pub fn min_price(&self) -> usize {
-        if self.prices.len() > 0 {
+        if !self.prices.is_empty() {
             let min = self.prices.iter().next().unwrap();
             return *min.0;
         }
@@ -463,4 +611,193 @@ mod tests {
         let min_price_queue = order_side.min_price_queue_mut();
         assert_eq!(min_price_queue.unwrap().head().unwrap().id, id);
     }
+
+    #[test]
+    fn test_available_volume() {
+        let mut asks = OrderSide::new(Side::Sell);
+        let mut bids = OrderSide::new(Side::Buy);
+        for (id, price) in [(1, 100), (2, 101), (3, 102)] {
+            asks.add_order(Order::new(
+                id,
+                1,
+                Side::Sell,
+                OrderType::Limit,
+                TimeInForce::GTC,
+                price,
+                10,
+            ));
+            bids.add_order(Order::new(
+                id,
+                1,
+                Side::Buy,
+                OrderType::Limit,
+                TimeInForce::GTC,
+                price,
+                10,
+            ));
+        }
+        assert_eq!(asks.available_volume(99, 100), 0);
+        assert_eq!(asks.available_volume(101, 100), 20);
+        assert_eq!(asks.available_volume(102, 100), 30);
+        assert_eq!(asks.available_volume(102, 5), 10);
+        assert_eq!(bids.available_volume(103, 100), 0);
+        assert_eq!(bids.available_volume(101, 100), 20);
+        assert_eq!(bids.available_volume(100, 15), 20);
+    }
+
+    #[test]
+    fn test_refill_order() {
+        let mut order_side = OrderSide::new(Side::Sell);
+        let mut iceberg = Order::new(
+            1,
+            1,
+            Side::Sell,
+            OrderType::Iceberg,
+            TimeInForce::GTC,
+            100,
+            10,
+        );
+        iceberg.peak_quantity = 10;
+        iceberg.reserve_quantity = 15;
+        let iceberg = order_side.add_order(iceberg);
+        order_side.add_order(Order::new(
+            2,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            5,
+        ));
+        assert_eq!(order_side.available_volume(100, 100), 30);
+
+        let refilled = order_side.refill_order(&iceberg).unwrap();
+        assert_eq!(refilled.quantity, 10);
+        assert_eq!(refilled.reserve_quantity, 5);
+        assert_eq!(order_side.volume, 15);
+        assert_eq!(order_side.num_orders, 2);
+        let order_queue = order_side.prices.get(&100).unwrap();
+        assert_eq!(order_queue.head().unwrap().id, 2);
+        assert_eq!(order_queue.volume, 15);
+        assert_eq!(order_queue.hidden_volume, 5);
+    }
+
+    #[test]
+    fn test_reference_price_skips_pegged_orders() {
+        use crate::order::{Peg, PegType};
+
+        let mut order_side = OrderSide::new(Side::Buy);
+        assert_eq!(order_side.reference_price(), None);
+        order_side.add_order(Order::new(
+            1,
+            1,
+            Side::Buy,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            10,
+        ));
+        let mut pegged = Order::new(2, 1, Side::Buy, OrderType::Limit, TimeInForce::GTC, 101, 10);
+        pegged.peg = Some(Peg {
+            peg_type: PegType::Primary,
+            offset: 1,
+        });
+        order_side.add_order(pegged);
+        assert_eq!(order_side.max_price(), 101);
+        assert_eq!(order_side.reference_price(), Some(100));
+    }
+
+    #[test]
+    fn test_reduce_order() {
+        let mut order_side = OrderSide::new(Side::Sell);
+        let first = order_side.add_order(Order::new(
+            1,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            10,
+        ));
+        order_side.add_order(Order::new(
+            2,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            10,
+        ));
+        let reduced = order_side.reduce_order(&first, 4).unwrap();
+        assert_eq!(reduced.quantity, 6);
+        assert_eq!(order_side.volume, 16);
+        assert_eq!(order_side.total, 1600);
+        let order_queue = order_side.prices.get(&100).unwrap();
+        assert_eq!(order_queue.head().unwrap().id, 1);
+        assert_eq!(order_queue.volume, 16);
+    }
+
+    #[test]
+    fn test_hidden_orders_not_displayed() {
+        let mut order_side = OrderSide::new(Side::Sell);
+        let mut hidden = Order::new(
+            1,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            10,
+        );
+        hidden.hidden = true;
+        order_side.add_order(hidden.clone());
+        order_side.add_order(Order::new(
+            2,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            101,
+            5,
+        ));
+        assert_eq!(order_side.volume, 5);
+        assert_eq!(order_side.min_price(), 100);
+        assert_eq!(order_side.displayed_price_queue().unwrap().price, 101);
+        assert_eq!(order_side.depth(5), vec![(101, 5)]);
+        assert_eq!(order_side.available_volume(101, 100), 15);
+
+        order_side.remove_order_internal(&hidden, &None);
+        assert_eq!(order_side.volume, 5);
+        assert_eq!(order_side.num_orders, 1);
+    }
+
+    #[test]
+    fn test_all_or_none_available_volume() {
+        let mut order_side = OrderSide::new(Side::Sell);
+        let mut order = Order::new(
+            1,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            100,
+            10,
+        );
+        order.all_or_none = true;
+        order_side.add_order(order);
+        order_side.add_order(Order::new(
+            2,
+            1,
+            Side::Sell,
+            OrderType::Limit,
+            TimeInForce::GTC,
+            101,
+            5,
+        ));
+        assert_eq!(order_side.available_volume(101, 8), 5);
+        assert_eq!(order_side.available_volume(101, 12), 15);
+        assert_eq!(order_side.next_price_queue(None).unwrap().price, 100);
+        assert_eq!(order_side.next_price_queue(Some(100)).unwrap().price, 101);
+        assert!(order_side.next_price_queue(Some(101)).is_none());
+    }
 }
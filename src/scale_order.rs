@@ -0,0 +1,139 @@
+// scale_order.rs
+use crate::{order::TimeInForce, side::Side};
+
+// how the total quantity of a scale order is spread over its levels, from the
+// start price towards the end price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Flat,
+    // level n gets n times the quantity of the first level
+    Linear,
+    // every level gets twice the quantity of the previous one
+    Exponential,
+}
+
+// a ladder of limit orders spread between two prices (inclusive)
+#[derive(Debug, Clone, Copy)]
+pub struct ScaleOrder {
+    pub side: Side,
+    pub quantity: usize,
+    pub start_price: usize,
+    pub end_price: usize,
+    pub levels: usize,
+    pub distribution: Distribution,
+    pub user_id: u32,
+    pub time_in_force: TimeInForce,
+}
+
+impl ScaleOrder {
+    // price and quantity of every child order, ordered from start to end price
+    pub fn ladder(&self) -> Result<Vec<(usize, usize)>, String> {
+        if self.levels == 0 {
+            return Err("Scale order needs at least one level".to_string());
+        }
+        if self.start_price == 0 || self.end_price == 0 {
+            return Err("Invalid price range".to_string());
+        }
+        if self.quantity < self.levels {
+            return Err("Quantity too small for levels".to_string());
+        }
+        let span = self.start_price.abs_diff(self.end_price);
+        if self.levels > 1 && span < self.levels - 1 {
+            return Err("Price range too narrow for levels".to_string());
+        }
+        if self.levels == 1 && span != 0 {
+            return Err("Single level scale order needs one price".to_string());
+        }
+
+        let weights: Vec<f64> = (0..self.levels)
+            .map(|level| match self.distribution {
+                Distribution::Flat => 1.0,
+                Distribution::Linear => (level + 1) as f64,
+                Distribution::Exponential => 2f64.powi(level as i32),
+            })
+            .collect();
+        let total_weight: f64 = weights.iter().sum();
+
+        let mut ladder = Vec::with_capacity(self.levels);
+        let mut cumulative_weight = 0.0;
+        let mut allocated = 0;
+        for (level, weight) in weights.iter().enumerate() {
+            let offset = if self.levels > 1 {
+                span * level / (self.levels - 1)
+            } else {
+                0
+            };
+            let price = if self.end_price >= self.start_price {
+                self.start_price + offset
+            } else {
+                self.start_price - offset
+            };
+            // rounding the running total keeps the sum of the levels exact
+            cumulative_weight += weight;
+            let target = if level + 1 == self.levels {
+                self.quantity
+            } else {
+                (self.quantity as f64 * cumulative_weight / total_weight).floor() as usize
+            };
+            let quantity = target.saturating_sub(allocated);
+            if quantity == 0 {
+                return Err("Quantity too small for levels".to_string());
+            }
+            allocated += quantity;
+            ladder.push((price, quantity));
+        }
+        Ok(ladder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale_order(quantity: usize, levels: usize, distribution: Distribution) -> ScaleOrder {
+        ScaleOrder {
+            side: Side::Buy,
+            quantity,
+            start_price: 100,
+            end_price: 96,
+            levels,
+            distribution,
+            user_id: 1,
+            time_in_force: TimeInForce::GTC,
+        }
+    }
+
+    #[test]
+    fn test_flat_ladder() {
+        let ladder = scale_order(10, 3, Distribution::Flat).ladder().unwrap();
+        assert_eq!(ladder, vec![(100, 3), (98, 3), (96, 4)]);
+    }
+
+    #[test]
+    fn test_linear_ladder() {
+        let ladder = scale_order(100, 5, Distribution::Linear).ladder().unwrap();
+        assert_eq!(
+            ladder,
+            vec![(100, 6), (99, 14), (98, 20), (97, 26), (96, 34)]
+        );
+        assert_eq!(ladder.iter().map(|(_, q)| q).sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn test_exponential_ladder() {
+        let ladder = scale_order(15, 4, Distribution::Exponential)
+            .ladder()
+            .unwrap();
+        assert_eq!(ladder, vec![(100, 1), (99, 2), (98, 4), (96, 8)]);
+    }
+
+    #[test]
+    fn test_invalid_ladder() {
+        assert!(scale_order(10, 0, Distribution::Flat).ladder().is_err());
+        assert!(scale_order(2, 3, Distribution::Flat).ladder().is_err());
+        assert!(scale_order(100, 6, Distribution::Flat).ladder().is_err());
+        assert!(scale_order(3, 3, Distribution::Exponential)
+            .ladder()
+            .is_err());
+    }
+}
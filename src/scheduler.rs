@@ -0,0 +1,279 @@
+// scheduler.rs
+use crate::{
+    clock::Clock,
+    order::{Order, OrderType, TimeInForce},
+    order_book::OrderBook,
+    side::Side,
+};
+use chrono::{DateTime, Utc};
+
+// how the parent quantity is spread over the schedule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    // equal slices spread evenly between start and end time
+    Twap { slices: usize },
+    // one slice per bucket of the volume profile, sized by the bucket's share
+    Vwap { profile: Vec<f64> },
+}
+
+#[derive(Debug, Clone)]
+pub struct ParentOrder {
+    pub user_id: u32,
+    pub side: Side,
+    pub quantity: usize,
+    // children are sent as IOC limit orders at this price, as market orders otherwise
+    pub limit_price: Option<usize>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub strategy: Strategy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChildOrder {
+    pub id: u32,
+    pub quantity: usize,
+    pub filled: usize,
+    pub sent_at: DateTime<Utc>,
+}
+
+// works a parent order into the book slice by slice as its clock moves on
+#[derive(Debug)]
+pub struct Scheduler {
+    parent: ParentOrder,
+    clock: Box<dyn Clock>,
+    // send time and cumulative target quantity of every slice
+    slices: Vec<(DateTime<Utc>, usize)>,
+    next_slice: usize,
+    children: Vec<ChildOrder>,
+}
+
+impl Scheduler {
+    pub fn new(parent: ParentOrder, clock: Box<dyn Clock>) -> Result<Self, String> {
+        if parent.quantity == 0 {
+            return Err("Invalid quantity".to_string());
+        }
+        if parent.end_time <= parent.start_time {
+            return Err("Schedule must end after it starts".to_string());
+        }
+        let weights = match &parent.strategy {
+            Strategy::Twap { slices } => vec![1.0; *slices],
+            Strategy::Vwap { profile } => profile.clone(),
+        };
+        let total_weight: f64 = weights.iter().sum();
+        // NaN compares false against everything, so check finiteness explicitly
+        let valid_weight = |weight: &f64| weight.is_finite() && *weight >= 0.0;
+        if weights.is_empty()
+            || !weights.iter().all(valid_weight)
+            || !total_weight.is_finite()
+            || total_weight <= 0.0
+        {
+            return Err("Invalid volume profile".to_string());
+        }
+
+        let duration = parent.end_time - parent.start_time;
+        let mut cumulative_weight = 0.0;
+        let slices = weights
+            .iter()
+            .enumerate()
+            .map(|(slice, weight)| {
+                cumulative_weight += weight;
+                let send_at = parent.start_time + duration * slice as i32 / weights.len() as i32;
+                let target = if slice + 1 == weights.len() {
+                    parent.quantity
+                } else {
+                    (parent.quantity as f64 * cumulative_weight / total_weight).floor() as usize
+                };
+                (send_at, target)
+            })
+            .collect();
+        Ok(Self {
+            parent,
+            clock,
+            slices,
+            next_slice: 0,
+            children: Vec::new(),
+        })
+    }
+
+    // sends what is due by the clock as one child order. The child asks for
+    // whatever it takes to catch up with the schedule, so quantity that did not
+    // fill rolls over into the next slice. Returns the id of the child sent
+    pub fn tick(&mut self, order_book: &mut OrderBook) -> Option<u32> {
+        let now = self.clock.now();
+        let due = self.slices[self.next_slice..]
+            .iter()
+            .take_while(|(send_at, _)| *send_at <= now)
+            .count();
+        if due == 0 {
+            return None;
+        }
+        self.next_slice += due;
+        let target = self.slices[self.next_slice - 1].1;
+        let quantity = target.saturating_sub(self.filled());
+        if quantity == 0 {
+            return None;
+        }
+
+        let id = order_book.next_order_id();
+        let parent = &self.parent;
+        let order = match parent.limit_price {
+            Some(limit_price) => Order::new(
+                id,
+                parent.user_id,
+                parent.side,
+                OrderType::Limit,
+                TimeInForce::IOC,
+                limit_price,
+                quantity,
+            ),
+            None => Order::new(
+                id,
+                parent.user_id,
+                parent.side,
+                OrderType::Market,
+                TimeInForce::IOC,
+                0,
+                quantity,
+            ),
+        };
+        let order = order_book.add_order(order);
+        let filled = match order.err {
+            Some(_) => 0,
+            None => quantity - order.quantity_left,
+        };
+        self.children.push(ChildOrder {
+            id,
+            quantity,
+            filled,
+            sent_at: now,
+        });
+        Some(id)
+    }
+
+    pub fn parent(&self) -> &ParentOrder {
+        &self.parent
+    }
+
+    pub fn children(&self) -> &[ChildOrder] {
+        &self.children
+    }
+
+    pub fn filled(&self) -> usize {
+        self.children.iter().map(|child| child.filled).sum()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.parent.quantity - self.filled()
+    }
+
+    // every slice has been sent
+    pub fn is_done(&self) -> bool {
+        self.next_slice == self.slices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::{Duration, TimeZone};
+
+    fn setup(
+        strategy: Strategy,
+        limit_price: Option<usize>,
+    ) -> (OrderBook, Scheduler, ManualClock) {
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let mut order_book = OrderBook::new("TEST".to_string(), true, None);
+        order_book.set_clock(Box::new(clock.clone()));
+        order_book.add_limit_order(Side::Sell, 100, 100, 2, 1, TimeInForce::GTC);
+        let parent = ParentOrder {
+            user_id: 1,
+            side: Side::Buy,
+            quantity: 40,
+            limit_price,
+            start_time: start,
+            end_time: start + Duration::minutes(4),
+            strategy,
+        };
+        let scheduler = Scheduler::new(parent, Box::new(clock.clone())).unwrap();
+        (order_book, scheduler, clock)
+    }
+
+    #[test]
+    fn test_twap_slices_over_time() {
+        let (mut order_book, mut scheduler, clock) = setup(Strategy::Twap { slices: 4 }, None);
+        assert!(scheduler.tick(&mut order_book).is_some());
+        assert!(scheduler.tick(&mut order_book).is_none());
+        assert_eq!(scheduler.filled(), 10);
+
+        // two slices became due at once and go out as one child
+        clock.advance(Duration::minutes(2));
+        scheduler.tick(&mut order_book);
+        assert_eq!(scheduler.children()[1].quantity, 20);
+        assert_eq!(scheduler.filled(), 30);
+
+        clock.advance(Duration::minutes(5));
+        scheduler.tick(&mut order_book);
+        assert!(scheduler.is_done());
+        assert_eq!(scheduler.remaining(), 0);
+        assert_eq!(order_book.last_trade_price(), Some(100));
+    }
+
+    #[test]
+    fn test_vwap_follows_volume_profile() {
+        let profile = vec![1.0, 3.0];
+        let (mut order_book, mut scheduler, clock) = setup(Strategy::Vwap { profile }, None);
+        scheduler.tick(&mut order_book);
+        assert_eq!(scheduler.filled(), 10);
+        clock.advance(Duration::minutes(2));
+        scheduler.tick(&mut order_book);
+        assert_eq!(scheduler.children()[1].quantity, 30);
+        assert!(scheduler.is_done());
+    }
+
+    #[test]
+    fn test_unfilled_quantity_rolls_over() {
+        let (mut order_book, mut scheduler, clock) = setup(Strategy::Twap { slices: 2 }, Some(99));
+        scheduler.tick(&mut order_book);
+        assert_eq!(scheduler.children()[0].filled, 0);
+        order_book.add_limit_order(Side::Sell, 100, 99, 2, 2, TimeInForce::GTC);
+        clock.advance(Duration::minutes(2));
+        scheduler.tick(&mut order_book);
+        assert_eq!(scheduler.children()[1].quantity, 40);
+        assert_eq!(scheduler.filled(), 40);
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        let start = Utc.with_ymd_and_hms(2023, 11, 15, 9, 30, 0).unwrap();
+        let parent = ParentOrder {
+            user_id: 1,
+            side: Side::Buy,
+            quantity: 40,
+            limit_price: None,
+            start_time: start,
+            end_time: start + Duration::minutes(4),
+            strategy: Strategy::Vwap { profile: vec![] },
+        };
+        let clock = ManualClock::new(start);
+        assert!(Scheduler::new(parent.clone(), Box::new(clock.clone())).is_err());
+        for profile in [
+            vec![1.0, f64::NAN],
+            vec![f64::INFINITY, 1.0],
+            vec![f64::MAX, f64::MAX],
+        ] {
+            let parent = ParentOrder {
+                strategy: Strategy::Vwap { profile },
+                ..parent.clone()
+            };
+            assert!(Scheduler::new(parent, Box::new(clock.clone())).is_err());
+        }
+        let parent = ParentOrder {
+            end_time: start,
+            strategy: Strategy::Twap { slices: 2 },
+            ..parent
+        };
+        assert!(Scheduler::new(parent, Box::new(clock)).is_err());
+    }
+}
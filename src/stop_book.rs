@@ -0,0 +1,218 @@
+// stop_book.rs
+use crate::{
+    order::{Order, OrderType},
+    side::Side,
+};
+use std::collections::BTreeMap;
+
+// stop orders waiting for the last trade price to reach their stop price,
+// they are kept out of the bids/asks until they trigger
+#[derive(Debug, Clone, Default)]
+pub struct StopBook {
+    buy_stops: BTreeMap<usize, Vec<Order>>,
+    sell_stops: BTreeMap<usize, Vec<Order>>,
+    // their stop price moves with the market, so they are kept in arrival order
+    // instead of being keyed by it
+    trailing_stops: Vec<Order>,
+    pub num_orders: usize,
+}
+
+impl StopBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flush(&mut self) {
+        self.buy_stops.clear();
+        self.sell_stops.clear();
+        self.trailing_stops.clear();
+        self.num_orders = 0;
+    }
+
+    pub fn add_order(&mut self, order: Order) {
+        if order.order_type == OrderType::TrailingStop {
+            self.trailing_stops.push(order);
+            self.num_orders += 1;
+            return;
+        }
+        let stops = match order.side {
+            Side::Buy => &mut self.buy_stops,
+            Side::Sell => &mut self.sell_stops,
+        };
+        stops.entry(order.stop_price).or_default().push(order);
+        self.num_orders += 1;
+    }
+
+    pub fn remove_order(&mut self, order: &Order) -> Option<Order> {
+        if order.order_type == OrderType::TrailingStop {
+            let index = self.trailing_stops.iter().position(|x| x.id == order.id)?;
+            self.num_orders -= 1;
+            return Some(self.trailing_stops.remove(index));
+        }
+        let stops = match order.side {
+            Side::Buy => &mut self.buy_stops,
+            Side::Sell => &mut self.sell_stops,
+        };
+        let level = stops.get_mut(&order.stop_price)?;
+        let index = level.iter().position(|x| x.id == order.id)?;
+        let removed_order = level.remove(index);
+        if level.is_empty() {
+            stops.remove(&order.stop_price);
+        }
+        self.num_orders -= 1;
+        Some(removed_order)
+    }
+
+    pub fn reduce_order(&mut self, order: &Order, quantity: usize) -> Option<Order> {
+        let stop = self.find_mut(order)?;
+        stop.quantity -= quantity.min(stop.quantity);
+        Some(stop.clone())
+    }
+
+    pub fn increase_order(&mut self, order: &Order, quantity: usize) -> Option<Order> {
+        let stop = self.find_mut(order)?;
+        stop.quantity += quantity;
+        Some(stop.clone())
+    }
+
+    fn find_mut(&mut self, order: &Order) -> Option<&mut Order> {
+        let level = match (order.order_type, order.side) {
+            (OrderType::TrailingStop, _) => &mut self.trailing_stops,
+            (_, Side::Buy) => self.buy_stops.get_mut(&order.stop_price)?,
+            (_, Side::Sell) => self.sell_stops.get_mut(&order.stop_price)?,
+        };
+        level.iter_mut().find(|x| x.id == order.id)
+    }
+
+    // takes out every stop triggered by a trade at `last_price`. Buy stops come
+    // first starting from the lowest stop price, then sell stops starting from the
+    // highest, orders sharing a stop price keep their arrival order. Trailing
+    // stops follow in arrival order
+    pub fn take_triggered(&mut self, last_price: usize) -> Vec<Order> {
+        let mut triggered = Vec::new();
+        let buy_prices: Vec<usize> = self
+            .buy_stops
+            .range(..=last_price)
+            .map(|(k, _)| *k)
+            .collect();
+        for price in buy_prices {
+            triggered.extend(self.buy_stops.remove(&price).unwrap());
+        }
+        let sell_prices: Vec<usize> = self
+            .sell_stops
+            .range(last_price..)
+            .rev()
+            .map(|(k, _)| *k)
+            .collect();
+        for price in sell_prices {
+            triggered.extend(self.sell_stops.remove(&price).unwrap());
+        }
+        let (trailing_triggered, trailing_stops) =
+            self.trailing_stops
+                .drain(..)
+                .partition(|order| match order.side {
+                    Side::Buy => last_price >= order.stop_price,
+                    Side::Sell => last_price <= order.stop_price,
+                });
+        self.trailing_stops = trailing_stops;
+        triggered.extend::<Vec<Order>>(trailing_triggered);
+        self.num_orders -= triggered.len();
+        triggered
+    }
+
+    // moves the watermark of trailing stops when a trade prints at a better price
+    // and returns the orders whose stop price changed
+    pub fn update_trailing(&mut self, trade_price: usize) -> Vec<Order> {
+        let mut updated = Vec::new();
+        for order in self.trailing_stops.iter_mut() {
+            if let Some(trailing_stop) = order.trailing_stop.as_mut() {
+                let improved = match order.side {
+                    Side::Buy => trade_price < trailing_stop.watermark,
+                    Side::Sell => trade_price > trailing_stop.watermark,
+                };
+                if improved {
+                    trailing_stop.watermark = trade_price;
+                    order.stop_price = trailing_stop.stop_price(order.side);
+                    updated.push(order.clone());
+                }
+            }
+        }
+        updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{TimeInForce, TrailingOffset, TrailingStop};
+
+    fn stop_order(id: u32, side: Side, stop_price: usize) -> Order {
+        let mut order = Order::new(id, 1, side, OrderType::StopMarket, TimeInForce::GTC, 0, 10);
+        order.stop_price = stop_price;
+        order
+    }
+
+    #[test]
+    fn test_add_and_remove_order() {
+        let mut stop_book = StopBook::new();
+        let order = stop_order(1, Side::Buy, 105);
+        stop_book.add_order(order.clone());
+        stop_book.add_order(stop_order(2, Side::Sell, 95));
+        assert_eq!(stop_book.num_orders, 2);
+        assert_eq!(stop_book.remove_order(&order).unwrap().id, 1);
+        assert!(stop_book.remove_order(&order).is_none());
+        assert_eq!(stop_book.num_orders, 1);
+    }
+
+    #[test]
+    fn test_take_triggered() {
+        let mut stop_book = StopBook::new();
+        stop_book.add_order(stop_order(1, Side::Buy, 105));
+        stop_book.add_order(stop_order(2, Side::Buy, 103));
+        stop_book.add_order(stop_order(3, Side::Buy, 103));
+        stop_book.add_order(stop_order(4, Side::Buy, 110));
+        stop_book.add_order(stop_order(5, Side::Sell, 95));
+        assert!(stop_book.take_triggered(100).is_empty());
+
+        let triggered: Vec<u32> = stop_book.take_triggered(105).iter().map(|o| o.id).collect();
+        assert_eq!(triggered, vec![2, 3, 1]);
+        assert_eq!(stop_book.num_orders, 2);
+
+        let triggered: Vec<u32> = stop_book.take_triggered(90).iter().map(|o| o.id).collect();
+        assert_eq!(triggered, vec![5]);
+        assert_eq!(stop_book.num_orders, 1);
+    }
+
+    #[test]
+    fn test_update_trailing() {
+        let mut stop_book = StopBook::new();
+        let mut order = stop_order(1, Side::Sell, 95);
+        order.order_type = OrderType::TrailingStop;
+        order.trailing_stop = Some(TrailingStop {
+            offset: TrailingOffset::Fixed(5),
+            watermark: 100,
+            limit_offset: None,
+        });
+        stop_book.add_order(order);
+
+        assert!(stop_book.update_trailing(99).is_empty());
+        let updated = stop_book.update_trailing(110);
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].stop_price, 105);
+        assert_eq!(updated[0].trailing_stop.unwrap().watermark, 110);
+
+        assert!(stop_book.take_triggered(106).is_empty());
+        assert_eq!(stop_book.take_triggered(105)[0].id, 1);
+        assert_eq!(stop_book.num_orders, 0);
+    }
+
+    #[test]
+    fn test_reduce_order() {
+        let mut stop_book = StopBook::new();
+        let order = stop_order(1, Side::Sell, 95);
+        stop_book.add_order(order.clone());
+        assert_eq!(stop_book.reduce_order(&order, 4).unwrap().quantity, 6);
+        assert_eq!(stop_book.increase_order(&order, 2).unwrap().quantity, 8);
+        assert_eq!(stop_book.take_triggered(95)[0].quantity, 8);
+    }
+}